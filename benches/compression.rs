@@ -0,0 +1,153 @@
+use std::hint::black_box;
+
+use binarygcode::{
+    ascii_to_binary_with_options, serialise_block_with_level,
+    AsciiToBinaryOptions, BlockKind, Checksum, CompressionAlgorithm,
+    CompressionLevel, Encoding,
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// The files compared, a PrusaSlicer 2.6 file with PNG thumbnails and a
+/// PrusaSlicer 2.8 file with QOI thumbnails and silent mode progress.
+static FIXTURES: [(&str, &str); 2] = [
+    (
+        "mini_cube_b",
+        include_str!("../test_files/mini_cube_b.gcode"),
+    ),
+    (
+        "mini_cube_ps2.8.1",
+        include_str!("../test_files/mini_cube_ps2.8.1.gcode"),
+    ),
+];
+
+static LEVELS: [(&str, CompressionLevel); 3] = [
+    ("fast", CompressionLevel::Fast),
+    ("balanced", CompressionLevel::Balanced),
+    ("max", CompressionLevel::Max),
+];
+
+/// A gcode chunk the size of the blocks written by `ascii_to_binary`.
+fn gcode_chunk(gcode: &'static str) -> &'static [u8] {
+    let bytes = gcode.as_bytes();
+    let mut end = bytes.len().min(u16::MAX as usize - 100);
+    while bytes[end - 1] != b'\n' {
+        end -= 1;
+    }
+    &bytes[..end]
+}
+
+fn serialise_block_benchmark(c: &mut Criterion) {
+    for (fixture, gcode) in FIXTURES {
+        serialise_block_group(c, fixture, gcode_chunk(gcode));
+    }
+}
+
+fn serialise_block_group(
+    c: &mut Criterion,
+    fixture: &str,
+    chunk: &'static [u8],
+) {
+    let mut group = c.benchmark_group(format!("serialise_block/{}", fixture));
+    group.throughput(Throughput::Bytes(chunk.len() as u64));
+
+    for compression in CompressionAlgorithm::ALL {
+        for (name, level) in LEVELS {
+            // Only deflate is affected by the level.
+            if compression != CompressionAlgorithm::Deflate && name != "max" {
+                continue;
+            }
+            let block = serialise_block_with_level(
+                BlockKind::GCode,
+                compression,
+                level,
+                Encoding::Ascii,
+                Checksum::Crc32,
+                &[],
+                chunk,
+            )
+            .unwrap();
+            let id = format!("{:?}/{}", compression, name);
+            eprintln!(
+                "{}/{}: {} bytes -> {} bytes ({:.2}%)",
+                fixture,
+                id,
+                chunk.len(),
+                block.len(),
+                block.len() as f64 / chunk.len() as f64 * 100.0
+            );
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    serialise_block_with_level(
+                        BlockKind::GCode,
+                        compression,
+                        level,
+                        Encoding::Ascii,
+                        Checksum::Crc32,
+                        &[],
+                        black_box(chunk),
+                    )
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn ascii_to_binary_benchmark(c: &mut Criterion) {
+    for (fixture, gcode) in FIXTURES {
+        ascii_to_binary_group(c, fixture, gcode);
+    }
+}
+
+fn ascii_to_binary_group(
+    c: &mut Criterion,
+    fixture: &str,
+    gcode: &'static str,
+) {
+    let mut group = c.benchmark_group(format!("ascii_to_binary/{}", fixture));
+    group.throughput(Throughput::Bytes(gcode.len() as u64));
+    group.sample_size(10);
+
+    let mut profiles: Vec<(String, AsciiToBinaryOptions)> = LEVELS
+        .iter()
+        .map(|(name, level)| {
+            let options = AsciiToBinaryOptions {
+                level: *level,
+                gcode_compression: CompressionAlgorithm::Deflate,
+                ..Default::default()
+            };
+            (format!("deflate/{}", name), options)
+        })
+        .collect();
+    profiles.push(("default".into(), AsciiToBinaryOptions::default()));
+    profiles.push((
+        "auto".into(),
+        AsciiToBinaryOptions {
+            auto_compression: Some(Vec::new()),
+            ..Default::default()
+        },
+    ));
+
+    for (name, options) in profiles {
+        let binary = ascii_to_binary_with_options(gcode, &options).unwrap();
+        eprintln!(
+            "{}/{}: {} bytes -> {} bytes ({:.2}%)",
+            fixture,
+            name,
+            gcode.len(),
+            binary.len(),
+            binary.len() as f64 / gcode.len() as f64 * 100.0
+        );
+        group.bench_function(name, |b| {
+            b.iter(|| ascii_to_binary_with_options(black_box(gcode), &options))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    serialise_block_benchmark,
+    ascii_to_binary_benchmark
+);
+criterion_main!(benches);
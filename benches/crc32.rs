@@ -0,0 +1,22 @@
+use std::hint::black_box;
+
+use binarygcode::Crc32;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+static BINARY: &[u8] = include_bytes!("../test_files/mini_cube_b.bgcode");
+
+fn crc32_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc32");
+    group.throughput(Throughput::Bytes(BINARY.len() as u64));
+    group.bench_function("update", |b| {
+        b.iter(|| {
+            let mut hasher = Crc32::new();
+            hasher.update(black_box(BINARY));
+            hasher.finalize()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, crc32_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,236 @@
+use std::{
+    fs::{self, File},
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use super::{
+    convert::{convert_data, ConvertArgs},
+    is_binary, read, write, CliError,
+};
+
+/// What happened to a file in a batch.
+enum Outcome {
+    Converted {
+        input: usize,
+        output: usize,
+    },
+    /// The output was newer than the input.
+    Skipped,
+    Failed(CliError),
+}
+
+/// Add the files to convert from a path. Directories add their files with
+/// the extension, and those of their subdirectories when recursive.
+fn collect(
+    path: &Path,
+    args: &ConvertArgs,
+    top: bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), CliError> {
+    if !path.is_dir() {
+        if top
+            || path
+                .extension()
+                .is_some_and(|e| e == args.extension.as_str())
+        {
+            files.push(path.to_owned());
+        }
+        return Ok(());
+    }
+    if !top && !args.recursive {
+        return Ok(());
+    }
+    let err = |e| CliError::Io(path.to_owned(), e);
+    let mut entries = fs::read_dir(path)
+        .map_err(err)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(err)?;
+    entries.sort();
+    for entry in entries {
+        collect(&entry, args, false, files)?;
+    }
+    Ok(())
+}
+
+/// Return whether a file starts with the binary gcode magic.
+fn file_is_binary(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let read = File::open(path)?.read(&mut magic)?;
+    Ok(is_binary(&magic[..read]))
+}
+
+/// Return whether an output was written after its input.
+fn up_to_date(
+    input: &Path,
+    output: &Path,
+) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    matches!((modified(input), modified(output)), (Some(i), Some(o)) if o >= i)
+}
+
+fn convert_file(
+    input: &Path,
+    force: bool,
+) -> Result<Outcome, CliError> {
+    let binary =
+        file_is_binary(input).map_err(|e| CliError::Io(input.to_owned(), e))?;
+    let output = input.with_extension(if binary { "gcode" } else { "bgcode" });
+    if output == input {
+        return Err(CliError::Unsupported(
+            input.to_owned(),
+            "the output would replace the input",
+        ));
+    }
+    if !force && up_to_date(input, &output) {
+        return Ok(Outcome::Skipped);
+    }
+    let data = read(input)?;
+    let (converted, _) = convert_data(input, &data)?;
+    // Write next to the output and move it into place so an interrupted
+    // write does not leave a truncated file that looks up to date.
+    let mut temporary = output.clone().into_os_string();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    write(&temporary, &converted)?;
+    if let Err(e) = fs::rename(&temporary, &output) {
+        let _ = fs::remove_file(&temporary);
+        return Err(CliError::Io(output, e));
+    }
+    Ok(Outcome::Converted {
+        input: data.len(),
+        output: converted.len(),
+    })
+}
+
+/// Convert many files across threads, carrying on past failures and
+/// finishing with a summary of each file.
+pub fn convert_batch(args: &ConvertArgs) -> Result<(), CliError> {
+    let mut files = Vec::new();
+    for input in &args.inputs {
+        collect(input, args, true, &mut files)?;
+    }
+    let jobs = args
+        .jobs
+        .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .clamp(1, files.len().max(1));
+
+    let progress = io::stderr().is_terminal();
+    let next = AtomicUsize::new(0);
+    let mut outcomes: Vec<Option<Outcome>> =
+        files.iter().map(|_| None).collect();
+    thread::scope(|s| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (files, next) = (&files, &next);
+            s.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(i) else {
+                    break;
+                };
+                let outcome = convert_file(file, args.force)
+                    .unwrap_or_else(Outcome::Failed);
+                if sender.send((i, outcome)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        let mut failed = 0;
+        for (n, (i, outcome)) in receiver.iter().enumerate() {
+            failed += matches!(outcome, Outcome::Failed(_)) as usize;
+            outcomes[i] = Some(outcome);
+            if progress {
+                eprint!("\r{}/{} files, {} failed", n + 1, files.len(), failed);
+            }
+        }
+        if progress {
+            eprintln!();
+        }
+    });
+
+    let mut totals = [0usize; 2];
+    let (mut converted, mut skipped, mut failed) = (0, 0, 0);
+    eprintln!(
+        "{:<10} {:>12} {:>12} {:>7}  file",
+        "status", "input", "output", "ratio"
+    );
+    for (file, outcome) in files.iter().zip(&outcomes) {
+        match outcome {
+            Some(Outcome::Converted { input, output }) => {
+                converted += 1;
+                totals[0] += *input;
+                totals[1] += *output;
+                eprintln!(
+                    "{:<10} {:>12} {:>12} {:>6.1}%  {}",
+                    "converted",
+                    input,
+                    output,
+                    *output as f64 / *input as f64 * 100.0,
+                    file.display()
+                );
+            }
+            Some(Outcome::Skipped) => skipped += 1,
+            Some(Outcome::Failed(e)) => {
+                failed += 1;
+                eprintln!(
+                    "{:<10} {:>12} {:>12} {:>7}  {}",
+                    "failed", "", "", "", e
+                );
+            }
+            None => {}
+        }
+    }
+    eprintln!(
+        "{} converted, {} up to date, {} failed, {} bytes -> {} bytes",
+        converted, skipped, failed, totals[0], totals[1]
+    );
+
+    let codes = outcomes.iter().filter_map(|o| match o {
+        Some(Outcome::Failed(e)) => Some(e.exit_code()),
+        _ => None,
+    });
+    CliError::from_failures(codes, files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{convert_file, Outcome};
+    use crate::cli::CliError;
+
+    #[test]
+    fn convert_file_without_gcode_fails() {
+        let dir =
+            env::temp_dir().join(format!("bgcode-batch-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("empty.gcode");
+        fs::write(&input, "; no print here\n").unwrap();
+        assert!(matches!(
+            convert_file(&input, false),
+            Err(CliError::Unsupported(..))
+        ));
+        assert!(!dir.join("empty.bgcode").exists());
+
+        let input = dir.join("cube.gcode");
+        fs::write(&input, include_bytes!("../../test_files/mini_cube_b.gcode"))
+            .unwrap();
+        assert!(matches!(
+            convert_file(&input, false),
+            Ok(Outcome::Converted { .. })
+        ));
+        assert!(dir.join("cube.bgcode").exists());
+        assert!(!dir.join("cube.bgcode.tmp").exists());
+        assert!(matches!(convert_file(&input, false), Ok(Outcome::Skipped)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
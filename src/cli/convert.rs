@@ -0,0 +1,235 @@
+use std::{
+    path::{Path, PathBuf},
+    str,
+};
+
+use binarygcode::{
+    ascii_to_binary_with_statistics, binary_to_ascii, AsciiToBinaryOptions,
+    BlockKind, BlockStatistics, CompressionLevel, MachineLimits,
+};
+use clap::Args;
+
+use super::batch::convert_batch;
+
+use super::{
+    gcode_error, is_binary, output_path, parse_level, read, write, ChecksumArg,
+    CliError, Compression, ThumbnailFormatArg,
+};
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// The gcode files or directories, or `-` for stdin.
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+    /// Where to write the converted gcode of a single input, or `-` for
+    /// stdout. Defaults to the input with its extension swapped.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Search directories and their subdirectories.
+    #[arg(short, long)]
+    pub recursive: bool,
+    /// The extension of the files converted from directories.
+    #[arg(long, default_value = "gcode")]
+    pub extension: String,
+    /// Convert files in a batch even when the output is up to date.
+    #[arg(long)]
+    pub force: bool,
+    /// The number of files converted at once. Defaults to the number of
+    /// cpus.
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct EncodeArgs {
+    /// The ascii gcode file, or `-` for stdin.
+    input: PathBuf,
+    /// Where to write the binary gcode, or `-` for stdout. Defaults to the
+    /// input with a `.bgcode` extension.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[arg(long, value_enum, default_value = "crc32")]
+    checksum: ChecksumArg,
+    /// The compression effort: fast, balanced, max or a Deflate level.
+    #[arg(long, value_parser = parse_level, default_value = "max")]
+    level: CompressionLevel,
+    /// The compression of the file and printer metadata blocks.
+    #[arg(long, value_enum, default_value = "none")]
+    metadata_compression: Compression,
+    #[arg(long, value_enum, default_value = "none")]
+    thumbnail_compression: Compression,
+    #[arg(long, value_enum, default_value = "heatshrink11")]
+    gcode_compression: Compression,
+    #[arg(long, value_enum, default_value = "deflate")]
+    slicer_metadata_compression: Compression,
+    /// Compress each block with whichever of these algorithms is smallest,
+    /// or every algorithm when none are listed, and print what was chosen.
+    #[arg(long, value_enum, num_args = 0.., value_delimiter = ',')]
+    auto_compression: Option<Vec<Compression>>,
+    /// Transcode the thumbnails into this format.
+    #[arg(long, value_enum)]
+    thumbnail_format: Option<ThumbnailFormatArg>,
+    /// Replace the M73 progress lines with ones from the time estimator.
+    #[arg(long)]
+    regenerate_progress: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DecodeArgs {
+    /// The binary gcode file, or `-` for stdin.
+    input: PathBuf,
+    /// Where to write the ascii gcode, or `-` for stdout. Defaults to the
+    /// input with a `.gcode` extension.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Mark the start and end of each block with a comment.
+    #[arg(long)]
+    block_comments: bool,
+}
+
+/// Convert binary gcode to ascii and ascii gcode to binary with the
+/// default options. Several files or directories are converted as a batch.
+pub fn convert(args: ConvertArgs) -> Result<(), CliError> {
+    if args.inputs.len() > 1 || args.inputs.iter().any(|p| p.is_dir()) {
+        if args.output.is_some() {
+            return Err(CliError::Usage(String::from(
+                "-o can only be used with a single input",
+            )));
+        }
+        return convert_batch(&args);
+    }
+    let input = &args.inputs[0];
+    let data = read(input)?;
+    let (converted, extension) = convert_data(input, &data)?;
+    let output = output_path(input, args.output, extension);
+    write(&output, &converted)?;
+    report(data.len(), converted.len());
+    Ok(())
+}
+
+pub fn encode(args: EncodeArgs) -> Result<(), CliError> {
+    let data = read(&args.input)?;
+    let options = AsciiToBinaryOptions {
+        checksum: args.checksum.into(),
+        level: args.level,
+        metadata_compression: args.metadata_compression.into(),
+        thumbnail_compression: args.thumbnail_compression.into(),
+        gcode_compression: args.gcode_compression.into(),
+        slicer_metadata_compression: args.slicer_metadata_compression.into(),
+        auto_compression: args
+            .auto_compression
+            .map(|c| c.into_iter().map(Into::into).collect()),
+        thumbnail_format: args.thumbnail_format.map(Into::into),
+        regenerate_progress: args
+            .regenerate_progress
+            .then(MachineLimits::default),
+    };
+    let (binary, statistics) = encode_data(&args.input, &data, &options)?;
+    if options.auto_compression.is_some() {
+        report_blocks(&statistics);
+    }
+    let output = output_path(&args.input, args.output, "bgcode");
+    write(&output, &binary)?;
+    report(data.len(), binary.len());
+    Ok(())
+}
+
+pub fn decode(args: DecodeArgs) -> Result<(), CliError> {
+    let data = read(&args.input)?;
+    let gcode = decode_data(&args.input, &data, args.block_comments)?;
+    let output = output_path(&args.input, args.output, "gcode");
+    write(&output, gcode.as_bytes())?;
+    report(data.len(), gcode.len());
+    Ok(())
+}
+
+/// Convert some gcode to the other format with the default options,
+/// returning it along with the extension of its format. Ascii gcode that
+/// produces no gcode blocks is not converted.
+pub fn convert_data(
+    input: &Path,
+    data: &[u8],
+) -> Result<(Box<[u8]>, &'static str), CliError> {
+    match is_binary(data) {
+        true => {
+            let gcode = decode_data(input, data, false)?;
+            Ok((gcode.into_boxed_bytes(), "gcode"))
+        }
+        false => {
+            let options = AsciiToBinaryOptions::default();
+            let (binary, stats) = encode_data(input, data, &options)?;
+            if !stats.iter().any(|s| s.kind == BlockKind::GCode) {
+                return Err(CliError::Unsupported(
+                    input.to_owned(),
+                    "no gcode between M73 P0 and M73 P100 R0",
+                ));
+            }
+            Ok((binary, "bgcode"))
+        }
+    }
+}
+
+fn encode_data(
+    input: &Path,
+    data: &[u8],
+    options: &AsciiToBinaryOptions,
+) -> Result<(Box<[u8]>, Vec<BlockStatistics>), CliError> {
+    if is_binary(data) {
+        return Err(CliError::Unsupported(
+            input.to_owned(),
+            "already binary gcode",
+        ));
+    }
+    let gcode = str::from_utf8(data).map_err(|_| {
+        CliError::Unsupported(input.to_owned(), "not utf8 gcode")
+    })?;
+    ascii_to_binary_with_statistics(gcode, options).map_err(gcode_error(input))
+}
+
+fn decode_data(
+    input: &Path,
+    binary: &[u8],
+    with_block_comments: bool,
+) -> Result<Box<str>, CliError> {
+    if !is_binary(binary) {
+        return Err(CliError::Unsupported(
+            input.to_owned(),
+            "not binary gcode",
+        ));
+    }
+    binary_to_ascii(binary, with_block_comments).map_err(gcode_error(input))
+}
+
+/// Print the sizes before and after a conversion.
+fn report(
+    input_len: usize,
+    output_len: usize,
+) {
+    eprintln!(
+        "{} bytes -> {} bytes ({:.2}%)",
+        input_len,
+        output_len,
+        output_len as f64 / input_len as f64 * 100.0
+    );
+}
+
+/// Print the compression chosen for each block and the size of each
+/// candidate.
+fn report_blocks(statistics: &[BlockStatistics]) {
+    for s in statistics {
+        let candidates: Vec<String> = s
+            .candidates
+            .iter()
+            .map(|(c, len)| format!("{:?} {}", c, len))
+            .collect();
+        eprintln!(
+            "{:?}: {:?}, {} bytes -> {} bytes ({:.2}%), tried {}",
+            s.kind,
+            s.compression,
+            s.uncompressed_len,
+            s.block_len,
+            s.ratio() * 100.0,
+            candidates.join(", ")
+        );
+    }
+}
@@ -0,0 +1,582 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str,
+};
+
+use binarygcode::{
+    ascii_to_binary, parse_metadata, thumbnails, AsciiSections, BlockKind,
+    Crc32,
+};
+use clap::Args;
+
+use super::{gcode_error, is_binary, read, read_blocks, with_stdout, CliError};
+
+/// Above this many edits the differing gcode is shown as replaced outright
+/// rather than searched for a shorter edit, bounding the memory used.
+const MAX_EDITS: usize = 2048;
+
+/// The metadata blocks compared key by key, in file order.
+const METADATA_KINDS: [BlockKind; 4] = [
+    BlockKind::FileMetadata,
+    BlockKind::PrinterMetadata,
+    BlockKind::PrintMetadata,
+    BlockKind::SlicerMetadata,
+];
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// The original gcode file, ascii or binary, or `-` for stdin.
+    old: PathBuf,
+    /// The gcode file to compare it with, ascii or binary.
+    new: PathBuf,
+    /// Ignore comments in the gcode along with lines that become empty.
+    #[arg(long)]
+    ignore_comments: bool,
+    /// Ignore the `M73` progress lines in the gcode.
+    #[arg(long)]
+    ignore_progress: bool,
+    /// Print counts of the differences instead of a diff.
+    #[arg(long)]
+    summary: bool,
+    /// The number of unchanged gcode lines shown around each change.
+    #[arg(short = 'U', long, default_value_t = 3)]
+    context: usize,
+}
+
+/// The parts of a gcode file that are compared.
+struct Contents {
+    /// The key value pairs of each metadata block.
+    metadata: Vec<(BlockKind, Vec<(String, String)>)>,
+    /// Each thumbnail as its format, size and the crc32 of its image.
+    thumbnails: Vec<String>,
+    gcode: String,
+}
+
+/// An edit turning the old lines into the new lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Same,
+    Delete,
+    Insert,
+}
+
+/// Return an ascii file without the sections `encode` converts into
+/// metadata and thumbnail blocks, leaving all of the rest to compare as
+/// gcode.
+fn ascii_gcode(
+    ascii: &str,
+    sections: &AsciiSections,
+) -> String {
+    let mut blocks = sections.blocks();
+    blocks.sort_by_key(|(_, range)| range.start);
+    let mut gcode = String::with_capacity(ascii.len());
+    let mut kept = 0;
+    for (kind, range) in blocks {
+        if kind == BlockKind::GCode {
+            continue;
+        }
+        // Take the whole line a thumbnail starts on.
+        let start = ascii[..range.start].rfind('\n').map_or(0, |i| i + 1);
+        if start >= kept {
+            gcode.push_str(&ascii[kept..start]);
+        }
+        kept = kept.max(range.end);
+    }
+    gcode.push_str(&ascii[kept..]);
+    gcode
+}
+
+/// Decode a file whatever its format. The metadata and thumbnails of ascii
+/// gcode are read by converting it to binary gcode, so both sides are split
+/// into blocks the same way, while the rest of it is compared as gcode.
+fn load(path: &Path) -> Result<Contents, CliError> {
+    let data = read(path)?;
+    let (binary, ascii) = match is_binary(&data) {
+        true => (data.into_boxed_slice(), None),
+        false => {
+            let ascii = String::from_utf8(data).map_err(|_| {
+                CliError::Unsupported(path.to_owned(), "not utf8 gcode")
+            })?;
+            let sections =
+                AsciiSections::find(&ascii).map_err(gcode_error(path))?;
+            let binary = ascii_to_binary(&ascii).map_err(gcode_error(path))?;
+            (binary, Some(ascii_gcode(&ascii, &sections)))
+        }
+    };
+    let err = gcode_error(path);
+    let (_, blocks) = read_blocks(path, &binary)?;
+    let mut metadata = Vec::new();
+    let mut gcode = Vec::new();
+    for b in &blocks {
+        match b.block.kind {
+            BlockKind::GCode if ascii.is_none() => {
+                gcode.extend(b.block.gcode().map_err(&err)?)
+            }
+            BlockKind::GCode => {}
+            BlockKind::Thumbnail => {}
+            kind => {
+                let data = b.block.decompress().map_err(|e| err(e.into()))?;
+                let pairs = parse_metadata(&data)
+                    .map_err(&err)?
+                    .into_iter()
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect();
+                metadata.push((kind, pairs));
+            }
+        }
+    }
+    let thumbnails = thumbnails(&binary)
+        .map_err(&err)?
+        .iter()
+        .map(|t| {
+            let mut crc = Crc32::new();
+            crc.update(&t.data);
+            format!(
+                "{:?} {}x{} crc32 {:08x}",
+                t.format,
+                t.width,
+                t.height,
+                crc.finalize()
+            )
+        })
+        .collect();
+    let gcode = match ascii {
+        Some(ascii) => ascii,
+        None => String::from_utf8_lossy(&gcode).into_owned(),
+    };
+    Ok(Contents {
+        metadata,
+        thumbnails,
+        gcode,
+    })
+}
+
+/// Return the gcode lines that are compared.
+fn gcode_lines<'a>(
+    gcode: &'a str,
+    args: &DiffArgs,
+) -> Vec<&'a str> {
+    gcode
+        .lines()
+        .filter_map(|line| {
+            let line = match args.ignore_comments {
+                true => line.split(';').next().unwrap_or("").trim_end(),
+                false => line,
+            };
+            let command = line.split_whitespace().next();
+            match () {
+                _ if args.ignore_comments && command.is_none() => None,
+                _ if args.ignore_progress && command == Some("M73") => None,
+                _ => Some(line),
+            }
+        })
+        .collect()
+}
+
+/// Return a gcode line as it is compared. MeatPack drops the whitespace in
+/// commands, so it is left out of both sides.
+fn normalise(line: &str) -> String {
+    let (command, comment) = match line.split_once(';') {
+        Some((command, comment)) => (command, Some(comment.trim())),
+        None => (line, None),
+    };
+    let mut normalised: String =
+        command.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(comment) = comment {
+        normalised.push(';');
+        normalised.push_str(comment);
+    }
+    normalised
+}
+
+/// Return the pairs of a metadata block. Keys can repeat, so each is
+/// numbered by its occurrence in the block.
+fn numbered_pairs(
+    contents: &Contents,
+    kind: BlockKind,
+) -> Vec<(&str, usize, &str)> {
+    let mut numbered: Vec<(&str, usize, &str)> = Vec::new();
+    let block = contents.metadata.iter().find(|(k, _)| *k == kind);
+    for (key, value) in block.map_or(&[][..], |(_, pairs)| pairs) {
+        let n = numbered.iter().filter(|(k, ..)| k == key).count();
+        numbered.push((key, n, value));
+    }
+    numbered
+}
+
+/// Return the metadata lines removed and added between two files, keyed
+/// by block kind.
+fn metadata_changes(
+    old: &Contents,
+    new: &Contents,
+) -> Vec<(BlockKind, Vec<String>, Vec<String>)> {
+    let find = |pairs: &[(&str, usize, &str)], key: &str, n: usize| {
+        pairs
+            .iter()
+            .find(|(k, i, _)| *k == key && *i == n)
+            .map(|(.., v)| v.to_string())
+    };
+    let mut changes = Vec::new();
+    for kind in METADATA_KINDS {
+        let (old_pairs, new_pairs) =
+            (numbered_pairs(old, kind), numbered_pairs(new, kind));
+        let (mut removed, mut added) = (Vec::new(), Vec::new());
+        for &(key, n, value) in &old_pairs {
+            match find(&new_pairs, key, n) {
+                Some(v) if v == value => {}
+                Some(v) => {
+                    removed.push(format!("{} = {}", key, value));
+                    added.push(format!("{} = {}", key, v));
+                }
+                None => removed.push(format!("{} = {}", key, value)),
+            }
+        }
+        for &(key, n, value) in &new_pairs {
+            if find(&old_pairs, key, n).is_none() {
+                added.push(format!("{} = {}", key, value));
+            }
+        }
+        if !removed.is_empty() || !added.is_empty() {
+            changes.push((kind, removed, added));
+        }
+    }
+    changes
+}
+
+/// Find the shortest edit script between two sequences of lines with
+/// Myers' algorithm, after setting aside their common start and end.
+fn diff_lines(
+    a: &[&str],
+    b: &[&str],
+) -> Vec<Edit> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) =
+        (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut edits = vec![Edit::Same; prefix];
+    match myers(a_mid, b_mid) {
+        Some(middle) => edits.extend(middle),
+        None => {
+            edits.extend(vec![Edit::Delete; a_mid.len()]);
+            edits.extend(vec![Edit::Insert; b_mid.len()]);
+        }
+    }
+    edits.extend(vec![Edit::Same; suffix]);
+    edits
+}
+
+/// Myers' greedy diff, returning `None` when more than `MAX_EDITS` edits
+/// are needed.
+fn myers(
+    a: &[&str],
+    b: &[&str],
+) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // The furthest reaching x of each diagonal k before each round d,
+    // stored for the diagonals -d-1..=d+1 it can be read at.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let at = |k: isize| (k + offset) as usize;
+
+    for d in 0..=max.min(MAX_EDITS) as isize {
+        trace.push(v[at(-d - 1)..=at(d + 1)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = match k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)])
+            {
+                true => v[at(k + 1)],
+                false => v[at(k - 1)] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+/// Walk back through the rounds of Myers' algorithm to recover the edits.
+fn backtrack(
+    trace: &[Vec<isize>],
+    n: isize,
+    m: isize,
+) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = match k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Same);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(match x == prev_x {
+                true => Edit::Insert,
+                false => Edit::Delete,
+            });
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    edits.reverse();
+    edits
+}
+
+/// Write the edits as unified diff hunks with some lines of context.
+fn write_hunks(
+    w: &mut dyn Write,
+    a: &[&str],
+    b: &[&str],
+    edits: &[Edit],
+    context: usize,
+) -> io::Result<()> {
+    // Each edit along with the old and new line it is at.
+    let mut lines = Vec::with_capacity(edits.len());
+    let (mut i, mut j) = (0, 0);
+    for &edit in edits {
+        lines.push((edit, i, j));
+        match edit {
+            Edit::Same => (i, j) = (i + 1, j + 1),
+            Edit::Delete => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+    let changes: Vec<usize> = (0..lines.len())
+        .filter(|&p| lines[p].0 != Edit::Same)
+        .collect();
+
+    let mut start = 0;
+    while start < changes.len() {
+        // Join changes whose contexts would touch.
+        let mut end = start;
+        while end + 1 < changes.len()
+            && changes[end + 1] - changes[end] <= 2 * context + 1
+        {
+            end += 1;
+        }
+        let from = changes[start].saturating_sub(context);
+        let to = (changes[end] + context + 1).min(lines.len());
+        let hunk = &lines[from..to];
+        let old_len = hunk.iter().filter(|l| l.0 != Edit::Insert).count();
+        let new_len = hunk.iter().filter(|l| l.0 != Edit::Delete).count();
+        // An empty range starts at the line before it.
+        let (_, old_start, new_start) = hunk[0];
+        writeln!(
+            w,
+            "@@ -{},{} +{},{} @@",
+            old_start + (old_len > 0) as usize,
+            old_len,
+            new_start + (new_len > 0) as usize,
+            new_len
+        )?;
+        for &(edit, i, j) in hunk {
+            match edit {
+                Edit::Same => writeln!(w, " {}", a[i])?,
+                Edit::Delete => writeln!(w, "-{}", a[i])?,
+                Edit::Insert => writeln!(w, "+{}", b[j])?,
+            }
+        }
+        start = end + 1;
+    }
+    Ok(())
+}
+
+/// Compare the metadata, thumbnails and gcode of two files.
+pub fn diff(args: DiffArgs) -> Result<(), CliError> {
+    let (old, new) = (load(&args.old)?, load(&args.new)?);
+
+    let metadata = metadata_changes(&old, &new);
+    let removed_thumbnails: Vec<_> = old
+        .thumbnails
+        .iter()
+        .filter(|t| !new.thumbnails.contains(t))
+        .collect();
+    let added_thumbnails: Vec<_> = new
+        .thumbnails
+        .iter()
+        .filter(|t| !old.thumbnails.contains(t))
+        .collect();
+    let (a, b) = (
+        gcode_lines(&old.gcode, &args),
+        gcode_lines(&new.gcode, &args),
+    );
+    let (a_keys, b_keys): (Vec<String>, Vec<String>) = (
+        a.iter().map(|l| normalise(l)).collect(),
+        b.iter().map(|l| normalise(l)).collect(),
+    );
+    let edits = diff_lines(
+        &a_keys.iter().map(String::as_str).collect::<Vec<_>>(),
+        &b_keys.iter().map(String::as_str).collect::<Vec<_>>(),
+    );
+    let count = |edit| edits.iter().filter(|&&e| e == edit).count();
+    let (deleted, inserted) = (count(Edit::Delete), count(Edit::Insert));
+
+    let differ = !metadata.is_empty()
+        || !removed_thumbnails.is_empty()
+        || !added_thumbnails.is_empty()
+        || deleted + inserted > 0;
+
+    with_stdout(|w| {
+        if args.summary {
+            let (removed, added) = metadata
+                .iter()
+                .fold((0, 0), |(r, a), c| (r + c.1.len(), a + c.2.len()));
+            writeln!(
+                w,
+                "metadata: {} lines removed, {} added",
+                removed, added
+            )?;
+            writeln!(
+                w,
+                "thumbnails: {} removed, {} added",
+                removed_thumbnails.len(),
+                added_thumbnails.len()
+            )?;
+            return writeln!(
+                w,
+                "gcode: {} lines removed, {} added",
+                deleted, inserted
+            );
+        }
+        if !differ {
+            return Ok(());
+        }
+        writeln!(w, "--- {}", args.old.display())?;
+        writeln!(w, "+++ {}", args.new.display())?;
+        for (kind, removed, added) in &metadata {
+            writeln!(w, "@@ {:?} @@", kind)?;
+            for line in removed {
+                writeln!(w, "-{}", line)?;
+            }
+            for line in added {
+                writeln!(w, "+{}", line)?;
+            }
+        }
+        if !removed_thumbnails.is_empty() || !added_thumbnails.is_empty() {
+            writeln!(w, "@@ Thumbnail @@")?;
+            for t in &removed_thumbnails {
+                writeln!(w, "-{}", t)?;
+            }
+            for t in &added_thumbnails {
+                writeln!(w, "+{}", t)?;
+            }
+        }
+        write_hunks(w, &a, &b, &edits, args.context)
+    })?;
+
+    match differ {
+        true => Err(CliError::Differ),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{
+        ascii_gcode, ascii_to_binary, diff, diff_lines, normalise, write_hunks,
+        AsciiSections, DiffArgs, Edit,
+    };
+    use crate::cli::CliError;
+
+    #[test]
+    fn diff_finds_shortest_edits() {
+        let a = ["G28", "G1 X1", "G1 X2", "G1 X3", "M84"];
+        let b = ["G28", "G1 X2", "G1 X3", "G1 X4", "M84"];
+        let (a_keys, b_keys): (Vec<String>, Vec<String>) = (
+            a.iter().map(|l| normalise(l)).collect(),
+            b.iter().map(|l| normalise(l)).collect(),
+        );
+        let edits = diff_lines(
+            &a_keys.iter().map(String::as_str).collect::<Vec<_>>(),
+            &b_keys.iter().map(String::as_str).collect::<Vec<_>>(),
+        );
+        use Edit::*;
+        assert_eq!(edits, [Same, Delete, Same, Same, Insert, Same]);
+
+        let mut out = Vec::new();
+        write_hunks(&mut out, &a, &b, &edits, 1).unwrap();
+        let expected =
+            "@@ -1,5 +1,5 @@\n G28\n-G1 X1\n G1 X2\n G1 X3\n+G1 X4\n M84\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        assert_eq!(diff_lines(&[], &b), [Insert; 5]);
+        assert_eq!(diff_lines(&a, &a), [Same; 5]);
+    }
+
+    #[test]
+    fn diff_compares_ascii_gcode() {
+        let dir =
+            env::temp_dir().join(format!("bgcode-diff-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (old, new) = (dir.join("old.gcode"), dir.join("new.gcode"));
+        fs::write(&old, "G28\nG1 X1 Y2\n").unwrap();
+        fs::write(&new, "G28\nG1 X5 Y2\n").unwrap();
+        let args = |old, new| DiffArgs {
+            old,
+            new,
+            ignore_comments: false,
+            ignore_progress: false,
+            summary: true,
+            context: 3,
+        };
+        assert!(matches!(
+            diff(args(old.clone(), new.clone())),
+            Err(CliError::Differ)
+        ));
+        assert!(diff(args(old.clone(), old)).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // MeatPack drops the spaces in commands but keeps comments.
+        assert_eq!(normalise("G1 X1 Y2 ; move"), normalise("G1X1Y2;move"));
+        assert_ne!(normalise("G1 X1 ; a b"), normalise("G1 X1 ; ab"));
+
+        let ascii = "; generated by test\n\nG28\n; thumbnail begin 1x1 4\n; AAAA\n; thumbnail end\nM84\n";
+        let sections = AsciiSections::find(ascii).unwrap();
+        assert_eq!(ascii_gcode(ascii, &sections), "G28\n\nM84\n");
+    }
+
+    #[test]
+    fn diff_ascii_with_its_encoding() {
+        let dir =
+            env::temp_dir().join(format!("bgcode-encoded-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let gcode = include_str!("../../test_files/mini_cube_b.gcode");
+        let (old, new) = (dir.join("cube.gcode"), dir.join("cube.bgcode"));
+        fs::write(&old, gcode).unwrap();
+        fs::write(&new, ascii_to_binary(gcode).unwrap()).unwrap();
+        let args = DiffArgs {
+            old,
+            new,
+            ignore_comments: true,
+            ignore_progress: false,
+            summary: true,
+            context: 3,
+        };
+        assert!(diff(args).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
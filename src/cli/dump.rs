@@ -0,0 +1,282 @@
+use std::{io::Write, path::PathBuf};
+
+use binarygcode::{
+    BinaryGcodeError, BlockLayout, DeserialisedFileHeader, DeserialisedResult,
+    Deserialiser,
+};
+use clap::Args;
+
+use super::{describe, gcode_error, read, with_stdout, CliError};
+
+/// The bytes shown on each line of a hexdump.
+const HEX_LINE_LEN: usize = 16;
+
+#[derive(Args, Debug)]
+pub struct DumpArgs {
+    /// The binary gcode file, or `-` for stdin.
+    input: PathBuf,
+    /// Only dump the block at this index.
+    #[arg(short, long)]
+    block: Option<usize>,
+    /// Hexdump the payload of each block as it is stored.
+    #[arg(long)]
+    compressed: bool,
+    /// Hexdump the payload of each block after decompressing it.
+    #[arg(long)]
+    decompressed: bool,
+}
+
+/// Write the bytes as lines of offset, hex and printable ascii.
+fn hexdump(
+    w: &mut dyn Write,
+    bytes: &[u8],
+    offset: usize,
+) -> std::io::Result<()> {
+    for (i, line) in bytes.chunks(HEX_LINE_LEN).enumerate() {
+        let mut hex = String::new();
+        for (j, b) in line.iter().enumerate() {
+            // An extra space splits the line into two halves.
+            let gap = if j == HEX_LINE_LEN / 2 { "  " } else { " " };
+            hex.push_str(&format!("{}{:02x}", gap, b));
+        }
+        let text: String = line
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(
+            w,
+            "    {:08x} {:<width$}  |{}|",
+            offset + i * HEX_LINE_LEN,
+            hex,
+            text,
+            width = HEX_LINE_LEN * 3 + 1
+        )?;
+    }
+    Ok(())
+}
+
+/// Format bytes as space separated hex.
+fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    hex.join(" ")
+}
+
+fn write_header(
+    w: &mut dyn Write,
+    binary: &[u8],
+    header: &DeserialisedFileHeader,
+) -> std::io::Result<()> {
+    writeln!(w, "file header at {:#010x}, 10 bytes", 0)?;
+    writeln!(
+        w,
+        "  {:#010x} magic              {}  {:?}",
+        0,
+        hex(&binary[..4]),
+        String::from_utf8_lossy(&binary[..4])
+    )?;
+    writeln!(
+        w,
+        "  {:#010x} version            {}  {}",
+        4,
+        hex(&binary[4..8]),
+        header.version
+    )?;
+    writeln!(
+        w,
+        "  {:#010x} checksum type      {}  {:?}",
+        8,
+        hex(&binary[8..10]),
+        header.checksum
+    )
+}
+
+fn write_block(
+    w: &mut dyn Write,
+    binary: &[u8],
+    index: usize,
+    layout: &BlockLayout,
+) -> std::io::Result<()> {
+    let start = layout.range.start;
+    let field = |w: &mut dyn Write, offset: usize, len: usize, name, value| {
+        writeln!(
+            w,
+            "  {:#010x} {:<18} {}  {}",
+            offset,
+            name,
+            hex(&binary[offset..offset + len]),
+            value
+        )
+    };
+    writeln!(
+        w,
+        "block {} at {:#010x}, {} bytes",
+        index,
+        start,
+        layout.range.len()
+    )?;
+    field(w, start, 2, "type", format!("{:?}", layout.kind))?;
+    field(
+        w,
+        start + 2,
+        2,
+        "compression",
+        format!("{:?}", layout.compression),
+    )?;
+    field(
+        w,
+        start + 4,
+        4,
+        "uncompressed size",
+        layout.data_uncompressed_len.to_string(),
+    )?;
+    if let Some(len) = layout.data_compressed_len {
+        field(w, start + 8, 4, "compressed size", len.to_string())?;
+    }
+    let parameters = &binary[layout.parameters.clone()];
+    let encoding = match layout.block(binary) {
+        Ok(block) => format!("{:?}", block.encoding),
+        Err(e) => describe(&e),
+    };
+    field(
+        w,
+        layout.parameters.start,
+        parameters.len(),
+        "parameters",
+        encoding,
+    )?;
+    writeln!(
+        w,
+        "  {:#010x} {:<18} {} bytes",
+        layout.data.start,
+        "payload",
+        layout.data.len()
+    )?;
+    match layout.checksum {
+        Some((stored, computed)) => {
+            let status = if stored == computed { "ok" } else { "MISMATCH" };
+            writeln!(
+                w,
+                "  {:#010x} {:<18} {}  stored {:08x}, computed {:08x}, {}",
+                layout.data.end,
+                "checksum",
+                hex(&stored.to_le_bytes()),
+                stored,
+                computed,
+                status
+            )
+        }
+        None => writeln!(w, "  {:<29} none", "checksum"),
+    }
+}
+
+/// Print every field of the file header and block headers with its
+/// offset, optionally followed by hexdumps of the payloads.
+pub fn dump(args: DumpArgs) -> Result<(), CliError> {
+    let binary = read(&args.input)?;
+    let err = gcode_error(&args.input);
+    let mut deserialiser = Deserialiser::default();
+    deserialiser.digest(&binary[..binary.len().min(10)]);
+    let header = match deserialiser.deserialise().map_err(&err)? {
+        DeserialisedResult::FileHeader(header) => header,
+        _ => {
+            let missing = 10 - binary.len().min(10);
+            return Err(err(BinaryGcodeError::UnexpectedEof(missing)));
+        }
+    };
+
+    // Blocks with a bad checksum are still dumped, as finding them is the
+    // point, but one that can not be framed ends the dump.
+    let mut layouts = Vec::new();
+    let mut offset = 10;
+    let mut failure = None;
+    while offset < binary.len() {
+        match BlockLayout::read(&binary, offset, header.checksum) {
+            Ok(layout) => {
+                offset = layout.range.end;
+                layouts.push(layout);
+            }
+            Err(e) => {
+                failure = Some((offset, e));
+                break;
+            }
+        }
+    }
+    match args.block {
+        Some(i) if i >= layouts.len() => {
+            return Err(CliError::Usage(format!(
+                "{}: no block {}, the file has {}",
+                args.input.display(),
+                i,
+                layouts.len()
+            )));
+        }
+        _ => {}
+    }
+
+    with_stdout(|w| {
+        if args.block.is_none() {
+            write_header(w, &binary, &header)?;
+        }
+        for (i, layout) in layouts.iter().enumerate() {
+            if args.block.is_some_and(|b| b != i) {
+                continue;
+            }
+            write_block(w, &binary, i, layout)?;
+            if args.compressed {
+                writeln!(w, "  stored payload")?;
+                hexdump(w, &binary[layout.data.clone()], layout.data.start)?;
+            }
+            if args.decompressed {
+                let data = layout.block(&binary).and_then(|b| {
+                    b.decompress().map_err(BinaryGcodeError::from)
+                });
+                match data {
+                    Ok(data) => {
+                        writeln!(w, "  decompressed payload")?;
+                        hexdump(w, &data, 0)?;
+                    }
+                    Err(e) => {
+                        writeln!(w, "  decompressed payload: {}", describe(&e))?
+                    }
+                }
+            }
+        }
+        match &failure {
+            Some((offset, e)) if args.block.is_none() => {
+                writeln!(
+                    w,
+                    "block {} at {:#010x}: {}",
+                    layouts.len(),
+                    offset,
+                    describe(e)
+                )
+            }
+            _ => Ok(()),
+        }
+    })?;
+    match failure {
+        Some((_, e)) => Err(err(e)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hexdump_splits_lines() {
+        let mut out = Vec::new();
+        hexdump(&mut out, b"G1 X10 Y20 E.5\nM73 P0\n", 0x20).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("    00000020  47 31"));
+        assert!(lines[0].ends_with("|G1 X10 Y20 E.5.M|"));
+        assert!(lines[1].starts_with("    00000030  37 33"));
+        assert!(lines[1].ends_with("|73 P0.|"));
+    }
+}
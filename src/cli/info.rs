@@ -0,0 +1,199 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use binarygcode::{parse_metadata, BlockKind, Thumbnail};
+use clap::Args;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use super::{gcode_error, read, read_blocks, with_stdout, CliError};
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    /// The binary gcode file, or `-` for stdin.
+    input: PathBuf,
+    /// Print the summary as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+/// A summary of a binary gcode file.
+#[derive(Serialize)]
+struct FileInfo {
+    path: String,
+    size: usize,
+    version: u32,
+    checksum: String,
+    blocks: Vec<BlockInfo>,
+    thumbnails: Vec<ThumbnailInfo>,
+    /// The key value pairs of each metadata block by kind.
+    metadata: Ordered<Ordered<String>>,
+}
+
+#[derive(Serialize)]
+struct BlockInfo {
+    index: usize,
+    offset: usize,
+    /// The length of the block including its header and checksum.
+    length: usize,
+    kind: String,
+    compression: String,
+    encoding: String,
+    compressed_size: usize,
+    uncompressed_size: usize,
+    /// The compressed size relative to the uncompressed size.
+    ratio: f64,
+}
+
+#[derive(Serialize)]
+struct ThumbnailInfo {
+    block: usize,
+    format: String,
+    width: u16,
+    height: u16,
+    size: usize,
+}
+
+/// Key value pairs serialised as an object in file order.
+struct Ordered<V>(Vec<(String, V)>);
+
+impl<V: Serialize> Serialize for Ordered<V> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+fn file_info(args: &InfoArgs) -> Result<FileInfo, CliError> {
+    let binary = read(&args.input)?;
+    let err = gcode_error(&args.input);
+    let (header, blocks) = read_blocks(&args.input, &binary)?;
+    let mut info = FileInfo {
+        path: args.input.display().to_string(),
+        size: binary.len(),
+        version: header.version,
+        checksum: format!("{:?}", header.checksum),
+        blocks: Vec::with_capacity(blocks.len()),
+        thumbnails: Vec::new(),
+        metadata: Ordered(Vec::new()),
+    };
+    for (i, b) in blocks.iter().enumerate() {
+        let block = &b.block;
+        let ratio = match block.data_uncompressed_len {
+            0 => 1.0,
+            n => block.data.len() as f64 / n as f64,
+        };
+        info.blocks.push(BlockInfo {
+            index: i,
+            offset: b.range.start,
+            length: b.range.len(),
+            kind: format!("{:?}", block.kind),
+            compression: format!("{:?}", block.compression),
+            encoding: format!("{:?}", block.encoding),
+            compressed_size: block.data.len(),
+            uncompressed_size: block.data_uncompressed_len,
+            ratio,
+        });
+        match block.kind {
+            BlockKind::Thumbnail => {
+                let thumb = Thumbnail::from_block(block).map_err(&err)?;
+                info.thumbnails.push(ThumbnailInfo {
+                    block: i,
+                    format: format!("{:?}", thumb.format),
+                    width: thumb.width,
+                    height: thumb.height,
+                    size: thumb.data.len(),
+                });
+            }
+            BlockKind::GCode => {}
+            kind => {
+                let data = block.decompress().map_err(|e| err(e.into()))?;
+                let pairs = parse_metadata(&data)
+                    .map_err(&err)?
+                    .into_iter()
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect();
+                info.metadata
+                    .0
+                    .push((format!("{:?}", kind), Ordered(pairs)));
+            }
+        }
+    }
+    Ok(info)
+}
+
+pub fn info(args: InfoArgs) -> Result<(), CliError> {
+    let info = file_info(&args)?;
+    if args.json {
+        // Serialising plain structs and strings can not fail.
+        let json = serde_json::to_string_pretty(&info).unwrap_or_default();
+        return with_stdout(|w| writeln!(w, "{}", json));
+    }
+    with_stdout(|w| write_summary(&info, w))
+}
+
+/// Write the summary as text.
+fn write_summary(
+    info: &FileInfo,
+    w: &mut dyn Write,
+) -> io::Result<()> {
+    writeln!(w, "{}", info.path)?;
+    writeln!(
+        w,
+        "version {}, checksum {}, {} blocks, {} bytes",
+        info.version,
+        info.checksum,
+        info.blocks.len(),
+        info.size
+    )?;
+    writeln!(
+        w,
+        "{:>5} {:>9}  {:<16} {:<15} {:<21} {:>10} {:>12} {:>7}",
+        "block",
+        "offset",
+        "kind",
+        "compression",
+        "encoding",
+        "stored",
+        "uncompressed",
+        "ratio"
+    )?;
+    for b in &info.blocks {
+        writeln!(
+            w,
+            "{:>5} {:>9}  {:<16} {:<15} {:<21} {:>10} {:>12} {:>6.1}%",
+            b.index,
+            b.offset,
+            b.kind,
+            b.compression,
+            b.encoding,
+            b.compressed_size,
+            b.uncompressed_size,
+            b.ratio * 100.0
+        )?;
+    }
+    if !info.thumbnails.is_empty() {
+        writeln!(w, "\nthumbnails")?;
+        for t in &info.thumbnails {
+            writeln!(
+                w,
+                "  {} {}x{}, {} bytes",
+                t.format, t.width, t.height, t.size
+            )?;
+        }
+    }
+    for (kind, pairs) in &info.metadata.0 {
+        writeln!(w, "\n{}", kind)?;
+        for (key, value) in &pairs.0 {
+            writeln!(w, "  {} = {}", key, value)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use binarygcode::{get_metadata, parse_metadata, set_metadata, BlockKind};
+use clap::Subcommand;
+
+use super::{
+    gcode_error, read, read_blocks, with_stdout, write, CliError, MetadataKind,
+};
+
+#[derive(Subcommand, Debug)]
+pub enum MetadataCommand {
+    /// Print the value of a key, or every key when none is given.
+    Get {
+        /// The binary gcode file, or `-` for stdin.
+        input: PathBuf,
+        key: Option<String>,
+        /// The metadata block to read.
+        #[arg(long, value_enum, default_value = "print")]
+        kind: MetadataKind,
+    },
+    /// Set the value of a key, adding it if required.
+    Set {
+        /// The binary gcode file, or `-` for stdin.
+        input: PathBuf,
+        key: String,
+        value: String,
+        /// The metadata block to change.
+        #[arg(long, value_enum, default_value = "print")]
+        kind: MetadataKind,
+        /// Where to write the changed file, or `-` for stdout. Defaults to
+        /// the input.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+pub fn metadata(command: MetadataCommand) -> Result<(), CliError> {
+    match command {
+        MetadataCommand::Get {
+            input,
+            key: Some(key),
+            kind,
+        } => {
+            let binary = read(&input)?;
+            let value = get_metadata(&binary, kind.into(), &key)
+                .map_err(gcode_error(&input))?;
+            match value {
+                Some(value) => with_stdout(|w| writeln!(w, "{}", value))?,
+                None => {
+                    return Err(CliError::NotFound(
+                        input,
+                        format!("key {}", key),
+                    ))
+                }
+            }
+        }
+        MetadataCommand::Get {
+            input,
+            key: None,
+            kind,
+        } => {
+            let binary = read(&input)?;
+            let (_, blocks) = read_blocks(&input, &binary)?;
+            let kind = BlockKind::from(kind);
+            if let Some(b) = blocks.iter().find(|b| b.block.kind == kind) {
+                let data = b
+                    .block
+                    .decompress()
+                    .map_err(|e| gcode_error(&input)(e.into()))?;
+                let pairs =
+                    parse_metadata(&data).map_err(gcode_error(&input))?;
+                with_stdout(|w| {
+                    for (key, value) in pairs {
+                        writeln!(w, "{}={}", key, value)?;
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        MetadataCommand::Set {
+            input,
+            key,
+            value,
+            kind,
+            output,
+        } => {
+            let binary = read(&input)?;
+            let edited = set_metadata(&binary, kind.into(), &key, &value)
+                .map_err(gcode_error(&input))?;
+            write(output.as_ref().unwrap_or(&input), &edited)?;
+        }
+    }
+    Ok(())
+}
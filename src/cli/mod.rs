@@ -0,0 +1,357 @@
+mod batch;
+mod convert;
+mod diff;
+mod dump;
+mod info;
+mod metadata;
+mod recompress;
+mod thumbnails;
+mod verify;
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use binarygcode::{
+    deserialise_file, BinaryGcodeError, BlockKind, Checksum,
+    CompressionAlgorithm, CompressionLevel, DeserialisedFileHeader,
+    FramedBlock, ThumbnailFormat,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use thiserror::Error;
+
+/// Convert, inspect and edit binary gcode files.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, after_help = EXIT_CODES)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert gcode to the other format, detected from the file contents.
+    Convert(convert::ConvertArgs),
+    /// Convert ascii gcode to binary gcode.
+    Encode(convert::EncodeArgs),
+    /// Convert binary gcode to ascii gcode.
+    Decode(convert::DecodeArgs),
+    /// Summarise the blocks of a binary gcode file.
+    Info(info::InfoArgs),
+    /// Check binary gcode files against the specification.
+    Verify(verify::VerifyArgs),
+    /// Write the thumbnails of a binary gcode file to image files.
+    ExtractThumbnails(thumbnails::ExtractThumbnailsArgs),
+    /// Read or change the metadata of a binary gcode file.
+    #[command(subcommand)]
+    Metadata(metadata::MetadataCommand),
+    /// Change the compression and gcode encoding of a binary gcode file.
+    Recompress(recompress::RecompressArgs),
+    /// Print every header field of a binary gcode file with its offset.
+    Dump(dump::DumpArgs),
+    /// Compare the metadata, thumbnails and gcode of two gcode files.
+    Diff(diff::DiffArgs),
+}
+
+impl Cli {
+    pub fn run(self) -> Result<(), CliError> {
+        match self.command {
+            Command::Convert(args) => convert::convert(args),
+            Command::Encode(args) => convert::encode(args),
+            Command::Decode(args) => convert::decode(args),
+            Command::Info(args) => info::info(args),
+            Command::Verify(args) => verify::verify(args),
+            Command::ExtractThumbnails(args) => thumbnails::extract(args),
+            Command::Metadata(command) => metadata::metadata(command),
+            Command::Recompress(args) => recompress::recompress(args),
+            Command::Dump(args) => dump::dump(args),
+            Command::Diff(args) => diff::diff(args),
+        }
+    }
+}
+
+const EXIT_CODES: &str = "Exit codes:
+  1  the files differ, or some failed for different reasons
+  2  invalid arguments
+  3  a file could not be read or written
+  4  a file is corrupted or could not be parsed
+  5  a block failed its checksum
+  6  a file is not in a supported format
+  7  a metadata key was not found";
+
+/// The errors the commands can fail with.
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{}: {}", .0.display(), .1)]
+    Io(PathBuf, io::Error),
+    #[error("{}: {}", .0.display(), describe(.1))]
+    Gcode(PathBuf, BinaryGcodeError),
+    #[error("{}: {}", .0.display(), .1)]
+    Unsupported(PathBuf, &'static str),
+    #[error("{0}")]
+    Usage(String),
+    /// Something looked up in a file, e.g. a metadata key, is not in it.
+    #[error("{}: no {}", .0.display(), .1)]
+    NotFound(PathBuf, String),
+    /// The files compared by `diff` are different.
+    #[error("the files differ")]
+    Differ,
+    /// Some of the files in a batch failed, with the exit code they share.
+    #[error("{failed} of {total} files failed")]
+    Failed {
+        failed: usize,
+        total: usize,
+        code: i32,
+    },
+}
+
+impl CliError {
+    /// Return the exit code for the kind of failure, as listed in
+    /// [`EXIT_CODES`].
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Io(..) => 3,
+            CliError::Gcode(_, e) => gcode_exit_code(e),
+            CliError::Unsupported(..) => 6,
+            CliError::Usage(_) => 2,
+            CliError::NotFound(..) => 7,
+            CliError::Differ => 1,
+            CliError::Failed { code, .. } => *code,
+        }
+    }
+
+    /// Return an error for the failures of a batch of files from their
+    /// exit codes, keeping the code when they all share one.
+    pub fn from_failures(
+        codes: impl IntoIterator<Item = i32>,
+        total: usize,
+    ) -> Result<(), CliError> {
+        let codes: Vec<_> = codes.into_iter().collect();
+        let Some(&first) = codes.first() else {
+            return Ok(());
+        };
+        let code = match codes.iter().all(|&c| c == first) {
+            true => first,
+            false => 1,
+        };
+        Err(CliError::Failed {
+            failed: codes.len(),
+            total,
+            code,
+        })
+    }
+}
+
+/// Return the exit code for a library error.
+fn gcode_exit_code(e: &BinaryGcodeError) -> i32 {
+    match e {
+        BinaryGcodeError::InvalidChecksum(..) => 5,
+        BinaryGcodeError::InvalidMagic(_)
+        | BinaryGcodeError::UnsupportedVersion(_)
+        | BinaryGcodeError::InvalidChecksumType(_)
+        | BinaryGcodeError::UnsupportedBlockKind(_)
+        | BinaryGcodeError::UnsupportedEncoding(_)
+        | BinaryGcodeError::UnsupportedCompressionAlgorithm(_) => 6,
+        _ => 4,
+    }
+}
+
+/// Describe a library error in terms of what is wrong with the file.
+fn describe(e: &BinaryGcodeError) -> String {
+    match e {
+        BinaryGcodeError::InvalidMagic(_) => {
+            String::from("not a binary gcode file")
+        }
+        BinaryGcodeError::InvalidChecksum(stored, computed) => format!(
+            "corrupted block, the stored checksum {:08x} does not match the \
+             computed {:08x}",
+            stored, computed
+        ),
+        BinaryGcodeError::UnexpectedEof(n) => {
+            format!("truncated, {} more bytes expected", n)
+        }
+        BinaryGcodeError::UnsupportedVersion(v) => {
+            format!("unsupported version {}", v)
+        }
+        BinaryGcodeError::InvalidChecksumType(t) => {
+            format!("unsupported checksum type {}", t)
+        }
+        BinaryGcodeError::UnsupportedBlockKind(k) => {
+            format!("unsupported block type {}", k)
+        }
+        BinaryGcodeError::UnsupportedEncoding(e) => {
+            format!("unsupported block encoding {}", e)
+        }
+        BinaryGcodeError::UnsupportedCompressionAlgorithm(c) => {
+            format!("unsupported compression {}", c)
+        }
+        BinaryGcodeError::DecodeError(e) => {
+            format!("corrupted block, it failed to decompress ({})", e)
+        }
+        e => e.to_string(),
+    }
+}
+
+/// The compression algorithms as command line values.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Compression {
+    None,
+    Deflate,
+    Heatshrink11,
+    Heatshrink12,
+}
+
+impl From<Compression> for CompressionAlgorithm {
+    fn from(c: Compression) -> Self {
+        match c {
+            Compression::None => CompressionAlgorithm::None,
+            Compression::Deflate => CompressionAlgorithm::Deflate,
+            Compression::Heatshrink11 => CompressionAlgorithm::Heatshrink11_4,
+            Compression::Heatshrink12 => CompressionAlgorithm::Heatshrink12_4,
+        }
+    }
+}
+
+/// The checksums as command line values.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ChecksumArg {
+    None,
+    Crc32,
+}
+
+impl From<ChecksumArg> for Checksum {
+    fn from(c: ChecksumArg) -> Self {
+        match c {
+            ChecksumArg::None => Checksum::None,
+            ChecksumArg::Crc32 => Checksum::Crc32,
+        }
+    }
+}
+
+/// The thumbnail formats as command line values.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ThumbnailFormatArg {
+    Png,
+    Jpg,
+    Qoi,
+}
+
+impl From<ThumbnailFormatArg> for ThumbnailFormat {
+    fn from(f: ThumbnailFormatArg) -> Self {
+        match f {
+            ThumbnailFormatArg::Png => ThumbnailFormat::Png,
+            ThumbnailFormatArg::Jpg => ThumbnailFormat::Jpg,
+            ThumbnailFormatArg::Qoi => ThumbnailFormat::Qoi,
+        }
+    }
+}
+
+/// The metadata block kinds as command line values.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum MetadataKind {
+    File,
+    Printer,
+    Print,
+    Slicer,
+}
+
+impl From<MetadataKind> for BlockKind {
+    fn from(k: MetadataKind) -> Self {
+        match k {
+            MetadataKind::File => BlockKind::FileMetadata,
+            MetadataKind::Printer => BlockKind::PrinterMetadata,
+            MetadataKind::Print => BlockKind::PrintMetadata,
+            MetadataKind::Slicer => BlockKind::SlicerMetadata,
+        }
+    }
+}
+
+/// Parse a compression level from `fast`, `balanced`, `max` or a Deflate
+/// level from 0 to 10.
+pub fn parse_level(s: &str) -> Result<CompressionLevel, String> {
+    match s {
+        "fast" => Ok(CompressionLevel::Fast),
+        "balanced" => Ok(CompressionLevel::Balanced),
+        "max" => Ok(CompressionLevel::Max),
+        _ => match s.parse::<u8>() {
+            Ok(level) if level <= 10 => Ok(CompressionLevel::Deflate(level)),
+            _ => Err(String::from(
+                "expected fast, balanced, max or a level from 0 to 10",
+            )),
+        },
+    }
+}
+
+/// Return whether a path is `-`, meaning stdin or stdout.
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Return whether data starts with the binary gcode magic.
+fn is_binary(data: &[u8]) -> bool {
+    data.starts_with(b"GCDE")
+}
+
+/// Read a whole file, or stdin for `-`.
+fn read(path: &Path) -> Result<Vec<u8>, CliError> {
+    let err = |e| CliError::Io(path.to_owned(), e);
+    if is_stdio(path) {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data).map_err(err)?;
+        return Ok(data);
+    }
+    fs::read(path).map_err(err)
+}
+
+/// Write a whole file, or stdout for `-`.
+fn write(
+    path: &Path,
+    data: &[u8],
+) -> Result<(), CliError> {
+    let err = |e| CliError::Io(path.to_owned(), e);
+    if is_stdio(path) {
+        return with_stdout(|w| w.write_all(data));
+    }
+    fs::write(path, data).map_err(err)
+}
+
+/// Run a function that writes to stdout. A reader that stops early, e.g.
+/// `head`, is not an error.
+fn with_stdout(
+    f: impl FnOnce(&mut dyn Write) -> io::Result<()>
+) -> Result<(), CliError> {
+    let mut stdout = io::stdout().lock();
+    match f(&mut stdout).and_then(|_| stdout.flush()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        r => r.map_err(|e| CliError::Io(PathBuf::from("-"), e)),
+    }
+}
+
+/// Return the output path given with `-o`, otherwise stdout when reading
+/// from stdin or the input with another extension.
+fn output_path(
+    input: &Path,
+    output: Option<PathBuf>,
+    extension: &str,
+) -> PathBuf {
+    match output {
+        Some(output) => output,
+        None if is_stdio(input) => PathBuf::from("-"),
+        None => input.with_extension(extension),
+    }
+}
+
+/// Attach the path of the file being processed to a library error.
+fn gcode_error(path: &Path) -> impl Fn(BinaryGcodeError) -> CliError + '_ {
+    move |e| CliError::Gcode(path.to_owned(), e)
+}
+
+/// Deserialise a whole binary gcode file.
+fn read_blocks(
+    path: &Path,
+    binary: &[u8],
+) -> Result<(DeserialisedFileHeader, Vec<FramedBlock>), CliError> {
+    deserialise_file(binary).map_err(gcode_error(path))
+}
@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use binarygcode::{transcode, CompressionLevel, Encoding, TranscodeOptions};
+use clap::{Args, ValueEnum};
+
+use super::{gcode_error, parse_level, read, write, CliError, Compression};
+
+/// The gcode encodings as command line values.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum GcodeEncoding {
+    Ascii,
+    /// Meatpack without the comments.
+    Meatpack,
+    MeatpackComments,
+}
+
+impl From<GcodeEncoding> for Encoding {
+    fn from(e: GcodeEncoding) -> Self {
+        match e {
+            GcodeEncoding::Ascii => Encoding::Ascii,
+            GcodeEncoding::Meatpack => Encoding::Meatpack,
+            GcodeEncoding::MeatpackComments => Encoding::MeatpackWithComments,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct RecompressArgs {
+    /// The binary gcode file, or `-` for stdin.
+    input: PathBuf,
+    /// Where to write the recompressed file, or `-` for stdout. Defaults
+    /// to the input.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// The compression effort: fast, balanced, max or a Deflate level.
+    #[arg(long, value_parser = parse_level, default_value = "max")]
+    level: CompressionLevel,
+    /// The compression of the file, printer and print metadata blocks.
+    /// Blocks keep their compression when it is not given.
+    #[arg(long, value_enum)]
+    metadata_compression: Option<Compression>,
+    #[arg(long, value_enum)]
+    thumbnail_compression: Option<Compression>,
+    #[arg(long, value_enum)]
+    gcode_compression: Option<Compression>,
+    #[arg(long, value_enum)]
+    slicer_metadata_compression: Option<Compression>,
+    /// The encoding of the gcode blocks.
+    #[arg(long, value_enum)]
+    gcode_encoding: Option<GcodeEncoding>,
+}
+
+/// Change the compression and gcode encoding of the blocks of a file
+/// without converting it to ascii.
+pub fn recompress(args: RecompressArgs) -> Result<(), CliError> {
+    let binary = read(&args.input)?;
+    let options = TranscodeOptions {
+        level: args.level,
+        metadata_compression: args.metadata_compression.map(Into::into),
+        thumbnail_compression: args.thumbnail_compression.map(Into::into),
+        gcode_compression: args.gcode_compression.map(Into::into),
+        slicer_metadata_compression: args
+            .slicer_metadata_compression
+            .map(Into::into),
+        gcode_encoding: args.gcode_encoding.map(Into::into),
+    };
+    let out = transcode(&binary, &options).map_err(gcode_error(&args.input))?;
+    write(args.output.as_ref().unwrap_or(&args.input), &out)?;
+    eprintln!("{} bytes -> {} bytes", binary.len(), out.len());
+    Ok(())
+}
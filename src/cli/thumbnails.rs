@@ -0,0 +1,47 @@
+use std::{fs, path::PathBuf};
+
+use binarygcode::{thumbnails, ThumbnailFormat};
+use clap::Args;
+
+use super::{gcode_error, is_stdio, read, write, CliError};
+
+#[derive(Args, Debug)]
+pub struct ExtractThumbnailsArgs {
+    /// The binary gcode file, or `-` for stdin.
+    input: PathBuf,
+    /// The directory to write the images to. Defaults to the directory of
+    /// the input.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn extract(args: ExtractThumbnailsArgs) -> Result<(), CliError> {
+    let binary = read(&args.input)?;
+    let thumbs = thumbnails(&binary).map_err(gcode_error(&args.input))?;
+    let dir = match args.output {
+        Some(dir) => dir,
+        None => args.input.parent().map(PathBuf::from).unwrap_or_default(),
+    };
+    fs::create_dir_all(&dir).map_err(|e| CliError::Io(dir.clone(), e))?;
+    let stem = match is_stdio(&args.input) {
+        true => String::from("thumbnail"),
+        false => args
+            .input
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    for thumb in &thumbs {
+        let extension = match thumb.format {
+            ThumbnailFormat::Png => "png",
+            ThumbnailFormat::Jpg => "jpg",
+            ThumbnailFormat::Qoi => "qoi",
+        };
+        let name =
+            format!("{}_{}x{}.{}", stem, thumb.width, thumb.height, extension);
+        let path = dir.join(name);
+        write(&path, &thumb.data)?;
+        eprintln!("{}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+
+use binarygcode::verify as verify_binary;
+use clap::Args;
+
+use super::{describe, gcode_exit_code, read, with_stdout, CliError};
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// The binary gcode files.
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Print nothing and only set the exit code.
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+/// Check a file against the specification, printing each problem, and
+/// return the exit code of the first one.
+fn verify_file(
+    path: &Path,
+    quiet: bool,
+) -> Result<Option<i32>, CliError> {
+    let binary = read(path)?;
+    let problems = verify_binary(&binary);
+    if !quiet {
+        with_stdout(|w| {
+            for p in &problems {
+                let block = match p.block {
+                    Some(i) => format!("block {}", i),
+                    None => String::from("header"),
+                };
+                writeln!(
+                    w,
+                    "{}: {} at offset {}: {}",
+                    path.display(),
+                    block,
+                    p.offset,
+                    describe(&p.error)
+                )?;
+            }
+            match problems.len() {
+                0 => writeln!(w, "{}: ok", path.display()),
+                n => writeln!(w, "{}: failed, {} problems", path.display(), n),
+            }
+        })?;
+    }
+    Ok(problems.first().map(|p| gcode_exit_code(&p.error)))
+}
+
+/// Check the checksums, block order, encodings and payloads of files.
+pub fn verify(args: VerifyArgs) -> Result<(), CliError> {
+    let mut codes = Vec::new();
+    for path in &args.inputs {
+        match verify_file(path, args.quiet) {
+            Ok(None) => {}
+            Ok(Some(code)) => codes.push(code),
+            Err(e) => {
+                if !args.quiet {
+                    eprintln!("{}", e);
+                }
+                codes.push(e.exit_code());
+            }
+        }
+    }
+    if !args.quiet {
+        let total = args.inputs.len();
+        with_stdout(|w| {
+            writeln!(
+                w,
+                "{} passed, {} failed",
+                total - codes.len(),
+                codes.len()
+            )
+        })?;
+    }
+    CliError::from_failures(codes, args.inputs.len())
+}
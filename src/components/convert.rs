@@ -1,14 +1,21 @@
-use core::str;
+use core::{ops::Range, str};
 
 use crate::components::common::{
     BinaryGcodeError, BlockKind, Checksum, CompressionAlgorithm, Encoding,
 };
 use crate::components::deserialiser::{DeserialisedResult, Deserialiser};
-use crate::components::serialiser::{serialise_block, serialise_file_header};
+use crate::components::serialiser::{
+    serialise_block_auto, serialise_block_with_level, serialise_file_header,
+    BlockStatistics, CompressionLevel,
+};
+use crate::components::thumbnail::{Thumbnail, ThumbnailFormat};
+use crate::components::time::{regenerate_progress, MachineLimits};
 use alloc::string::ToString;
 use alloc::{borrow::ToOwned, boxed::Box, vec::Vec};
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use regex::Regex;
 
 /// Provide a reference to a u8 slice of the entire binary file
@@ -21,6 +28,11 @@ pub fn binary_to_ascii(
     let mut deserialiser = Deserialiser::default();
     deserialiser.digest(binary);
 
+    // Blocks are independent once framed so they are collected and
+    // decompressed across threads before being joined in order.
+    #[cfg(feature = "parallel")]
+    let mut blocks = Vec::new();
+
     // Loop through running deserialise on the deserialisers inner
     // buffer with it returning either a header, block or request for more bytes.
     // Or an error when deserialising.
@@ -28,19 +40,227 @@ pub fn binary_to_ascii(
         let r = deserialiser.deserialise()?;
         match r {
             DeserialisedResult::FileHeader(_) => {}
+            #[cfg(not(feature = "parallel"))]
             DeserialisedResult::Block(mut b) => {
                 b.to_ascii(&mut out, with_block_comments)?;
             }
+            #[cfg(feature = "parallel")]
+            DeserialisedResult::Block(b) => blocks.push(b),
             DeserialisedResult::MoreBytesRequired(_) => {
                 break;
             }
         }
     }
 
+    #[cfg(feature = "parallel")]
+    {
+        let parts = blocks
+            .into_par_iter()
+            .map(|mut b| {
+                let mut part = Vec::new();
+                b.to_ascii(&mut part, with_block_comments)?;
+                Ok(part)
+            })
+            .collect::<Result<Vec<Vec<u8>>, BinaryGcodeError>>()?;
+        for part in parts {
+            out.extend(part);
+        }
+    }
+
     let gcode = str::from_utf8(&out).unwrap().to_owned().into_boxed_str();
     Ok(gcode)
 }
 
+/// Where the parts of ascii gcode that become blocks are, found as
+/// [`ascii_to_binary`] finds them. Anything outside them is left out of the
+/// binary gcode.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AsciiSections {
+    pub file_metadata: Option<Range<usize>>,
+    pub printer_metadata: Option<Range<usize>>,
+    /// Each thumbnail from its `thumbnail begin` to its `; thumbnail end`.
+    pub thumbnails: Vec<Range<usize>>,
+    /// The totals the slicer writes after the gcode.
+    pub print_metadata: Option<Range<usize>>,
+    /// The PrusaSlicer config.
+    pub slicer_metadata: Option<Range<usize>>,
+    /// The gcode from `M73 P0` to `M73 P100 R0`.
+    pub gcode: Option<Range<usize>>,
+}
+
+impl AsciiSections {
+    /// Find the sections of ascii gcode. A section that starts without
+    /// ending is an error.
+    pub fn find(ascii: &str) -> Result<Self, BinaryGcodeError> {
+        let section = |start: Option<usize>, needle: &str, name| {
+            let Some(s) = start else {
+                return Ok(None);
+            };
+            match ascii[s..].find(needle) {
+                Some(e) => Ok(Some(s..s + e + needle.len())),
+                None => Err(BinaryGcodeError::SerialiseError(name)),
+            }
+        };
+
+        let mut thumbnails = Vec::new();
+        let mut offset = 0;
+        while let Some(start) = ascii[offset..].find("thumbnail begin") {
+            let start = offset + start;
+            let needle = "; thumbnail end";
+            let Some(end) = ascii[start..].find(needle) else {
+                return Err(BinaryGcodeError::SerialiseError("thumbnail"));
+            };
+            // continue along the str
+            offset = start + end + needle.len();
+            thumbnails.push(start..offset);
+        }
+
+        // A gcode section without its end is left out rather than an error.
+        let gcode = section(ascii.find("M73 P0"), "M73 P100 R0\n", "gcode")
+            .ok()
+            .flatten();
+
+        let printer_metadata =
+            section(ascii.find("; printer_model"), "\n\n", "printer_metadata")?;
+        // The header repeats some of the totals, so only the last ones
+        // outside it are taken.
+        let print_metadata = ascii.rfind("; filament used [mm]").filter(|s| {
+            !printer_metadata.as_ref().is_some_and(|r| r.contains(s))
+        });
+
+        Ok(Self {
+            file_metadata: section(
+                ascii.find("; generated by"),
+                "\n\n",
+                "file_metadata",
+            )?,
+            printer_metadata,
+            thumbnails,
+            print_metadata: section(print_metadata, "\n\n", "print_metadata")?,
+            slicer_metadata: section(
+                ascii.find("; prusaslicer_config = begin"),
+                "; prusaslicer_config = end",
+                "slicer_config",
+            )?,
+            gcode,
+        })
+    }
+
+    /// Return the kind and range of each block in the order they are
+    /// written, which is the order of the specification.
+    pub fn blocks(&self) -> Vec<(BlockKind, Range<usize>)> {
+        let mut blocks = Vec::new();
+        let mut add = |kind, range: &Option<Range<usize>>| {
+            if let Some(range) = range {
+                blocks.push((kind, range.clone()));
+            }
+        };
+        add(BlockKind::FileMetadata, &self.file_metadata);
+        add(BlockKind::PrinterMetadata, &self.printer_metadata);
+        for thumbnail in &self.thumbnails {
+            add(BlockKind::Thumbnail, &Some(thumbnail.clone()));
+        }
+        add(BlockKind::PrintMetadata, &self.print_metadata);
+        add(BlockKind::SlicerMetadata, &self.slicer_metadata);
+        add(BlockKind::GCode, &self.gcode);
+        blocks
+    }
+}
+
+/// A serialised block along with its statistics.
+type StatisticsBlock = (Box<[u8]>, BlockStatistics);
+
+/// The options used when converting ascii gcode to binary gcode.
+#[derive(Debug, Clone)]
+pub struct AsciiToBinaryOptions {
+    pub checksum: Checksum,
+    /// The compression effort used for every block.
+    pub level: CompressionLevel,
+    /// The compression of the file, printer and print metadata blocks.
+    pub metadata_compression: CompressionAlgorithm,
+    pub thumbnail_compression: CompressionAlgorithm,
+    pub gcode_compression: CompressionAlgorithm,
+    pub slicer_metadata_compression: CompressionAlgorithm,
+    /// When set, each block is compressed with whichever of these
+    /// algorithms produces the smallest block, overriding the per-kind
+    /// compressions above. An empty list tries every algorithm.
+    pub auto_compression: Option<Vec<CompressionAlgorithm>>,
+    /// When set, thumbnails in other formats are transcoded into this
+    /// format, e.g. slicer-provided PNGs into the QOI preferred by Prusa
+    /// firmware. Transcoding PNG and JPG requires the `images` feature.
+    pub thumbnail_format: Option<ThumbnailFormat>,
+    /// When set, the `M73` progress lines are replaced with ones from the
+    /// print time estimator starting from these limits.
+    pub regenerate_progress: Option<MachineLimits>,
+}
+
+impl Default for AsciiToBinaryOptions {
+    fn default() -> Self {
+        Self {
+            checksum: Checksum::Crc32,
+            level: CompressionLevel::default(),
+            metadata_compression: CompressionAlgorithm::None,
+            thumbnail_compression: CompressionAlgorithm::None,
+            gcode_compression: CompressionAlgorithm::Heatshrink11_4,
+            slicer_metadata_compression: CompressionAlgorithm::Deflate,
+            auto_compression: None,
+            thumbnail_format: None,
+            regenerate_progress: None,
+        }
+    }
+}
+
+impl AsciiToBinaryOptions {
+    /// Serialise a block with the compression defined by the options,
+    /// returning it along with its statistics.
+    fn serialise_block(
+        &self,
+        kind: BlockKind,
+        compression: CompressionAlgorithm,
+        encoding: Encoding,
+        additional_parameters: &[u8],
+        data: &[u8],
+    ) -> Result<StatisticsBlock, BinaryGcodeError> {
+        match &self.auto_compression {
+            Some(allowed) => {
+                let allowed = if allowed.is_empty() {
+                    None
+                } else {
+                    Some(allowed.as_slice())
+                };
+                serialise_block_auto(
+                    kind,
+                    allowed,
+                    self.level,
+                    encoding,
+                    self.checksum,
+                    additional_parameters,
+                    data,
+                )
+            }
+            None => {
+                let block = serialise_block_with_level(
+                    kind,
+                    compression,
+                    self.level,
+                    encoding,
+                    self.checksum,
+                    additional_parameters,
+                    data,
+                )?;
+                let stats = BlockStatistics {
+                    kind,
+                    compression,
+                    uncompressed_len: data.len(),
+                    block_len: block.len(),
+                    candidates: vec![(compression, block.len())],
+                };
+                Ok((block, stats))
+            }
+        }
+    }
+}
+
 /// Returns a bgcode from an ascii binary
 ///
 /// Notes:
@@ -48,129 +268,140 @@ pub fn binary_to_ascii(
 /// on the deserialise. Could add a check if they exist on the deserialise side
 /// and add them if not. And need to remove them on this side to save space??
 pub fn ascii_to_binary(ascii: &str) -> Result<Box<[u8]>, BinaryGcodeError> {
-    let mut binary: Vec<u8> = Vec::new();
-    let header = serialise_file_header(1, Checksum::Crc32);
-    binary.extend(header);
-
-    // File metadata
-    if let Some(start) = ascii.find("; generated by") {
-        let needle = "\n\n";
-        if let Some(end) = ascii[start..].find(needle) {
-            let block_data = &ascii[start..start + end + needle.len()];
-            let block = serialise_block(
-                BlockKind::FileMetadata,
-                CompressionAlgorithm::None,
-                Encoding::Ini,
-                Checksum::Crc32,
-                &[],
-                block_data.as_bytes(),
-            )?;
-            binary.extend(block);
-        } else {
-            return Err(BinaryGcodeError::SerialiseError("file_metadata"));
-        }
-    }
+    ascii_to_binary_with_options(ascii, &AsciiToBinaryOptions::default())
+}
 
-    // Printer Metadata
-    if let Some(start) = ascii.find("; printer_model") {
-        let needle = "\n\n";
-        if let Some(end) = ascii[start..].find(needle) {
-            let block_data = &ascii[start..start + end + needle.len()];
-            let block = serialise_block(
-                BlockKind::PrinterMetadata,
-                CompressionAlgorithm::None,
-                Encoding::Ini,
-                Checksum::Crc32,
-                &[],
-                block_data.as_bytes(),
-            )?;
-            binary.extend(block);
-        } else {
-            return Err(BinaryGcodeError::SerialiseError("printer_metadata"));
-        }
-    }
+/// Returns a bgcode from an ascii binary using the provided options to
+/// select the checksum and the compression of each block.
+pub fn ascii_to_binary_with_options(
+    ascii: &str,
+    options: &AsciiToBinaryOptions,
+) -> Result<Box<[u8]>, BinaryGcodeError> {
+    let (binary, _) = ascii_to_binary_with_statistics(ascii, options)?;
+    Ok(binary)
+}
 
-    // Thumbnails
-    let mut inner = ascii;
-    while let Some(start) = inner.find("thumbnail begin") {
-        let needle = "; thumbnail end";
-        if let Some(end) = inner[start..].find("; thumbnail end") {
-            let block =
-                thumbnail_block(&inner[start..start + end + needle.len()])?;
-            binary.extend(block);
-            // continue along the str
-            inner = &inner[start + end + needle.len()..];
-        } else {
-            return Err(BinaryGcodeError::SerialiseError("thumbnail"));
-        }
-    }
+/// Returns a bgcode from an ascii binary as `ascii_to_binary_with_options`
+/// does, along with the statistics of each block in file order, e.g. to
+/// see which algorithm `auto_compression` chose.
+pub fn ascii_to_binary_with_statistics(
+    ascii: &str,
+    options: &AsciiToBinaryOptions,
+) -> Result<(Box<[u8]>, Vec<BlockStatistics>), BinaryGcodeError> {
+    let mut binary: Vec<u8> = Vec::new();
+    binary.extend(serialise_file_header(1, options.checksum));
+    let mut statistics = Vec::new();
+    let mut push = |(block, stats): StatisticsBlock| {
+        binary.extend(block);
+        statistics.push(stats);
+    };
 
-    // Gcode
-    if let Some(start) = ascii.find("M73 P0") {
-        let needle = "M73 P100 R0\n";
-        if let Some(end) = ascii[start..].find(needle) {
-            let gcode = &ascii[start..start + end + needle.len()];
-            // Need to chunk it up to account for the u16 slice input buffer.
-            let mut chunk: Vec<u8> = Vec::new();
-            for b in gcode.as_bytes() {
-                chunk.push(*b);
-                // If the chunk is nearing max u16 and
-                // we reach a new line then encode it.
-                // TODO: decide what is a reasonable size gcode chunk
-                // and check against the libgcode reference.
-                if u16::MAX - (chunk.len() as u16) < 100 && *b == 10 {
-                    let block = serialise_block(
-                        BlockKind::GCode,
-                        CompressionAlgorithm::Heatshrink11_4,
-                        Encoding::Ascii,
-                        Checksum::Crc32,
-                        &[],
-                        &chunk,
-                    )?;
-                    binary.extend(block);
-                    chunk.clear();
+    for (kind, range) in AsciiSections::find(ascii)?.blocks() {
+        let data = &ascii[range];
+        match kind {
+            BlockKind::Thumbnail => push(thumbnail_block(data, options)?),
+            BlockKind::GCode => {
+                let gcode = data.as_bytes();
+                let regenerated = options
+                    .regenerate_progress
+                    .map(|limits| regenerate_progress(gcode, limits));
+                let gcode = regenerated.as_deref().unwrap_or(gcode);
+                // Need to chunk it up to account for the u16 slice input
+                // buffer.
+                let chunks = gcode_chunks(gcode);
+                for block in serialise_gcode_chunks(&chunks, options)? {
+                    push(block);
                 }
             }
-
-            // One remaining chunk
-            if !chunk.is_empty() {
-                let block = serialise_block(
-                    BlockKind::GCode,
-                    CompressionAlgorithm::Heatshrink11_4,
-                    Encoding::Ascii,
-                    Checksum::Crc32,
+            kind => {
+                let compression = match kind {
+                    BlockKind::SlicerMetadata => {
+                        options.slicer_metadata_compression
+                    }
+                    _ => options.metadata_compression,
+                };
+                push(options.serialise_block(
+                    kind,
+                    compression,
+                    Encoding::Ini,
                     &[],
-                    &chunk,
-                )?;
-                binary.extend(block);
-                chunk.clear();
+                    data.as_bytes(),
+                )?);
             }
         }
     }
 
-    // Slicer config (prusa slicer only atm)
-    if let Some(start) = ascii.find("; prusaslicer_config = begin") {
-        let needle = "; prusaslicer_config = end";
-        if let Some(end) = ascii[start..].find(needle) {
-            let block_data = &ascii[start..start + end + needle.len()];
-            let block = serialise_block(
-                BlockKind::SlicerMetadata,
-                CompressionAlgorithm::Deflate,
-                Encoding::Ini,
-                Checksum::Crc32,
-                &[],
-                block_data.as_bytes(),
-            )?;
-            binary.extend(block);
-        } else {
-            return Err(BinaryGcodeError::SerialiseError("slicer_config"));
+    Ok((binary.into_boxed_slice(), statistics))
+}
+
+/// Split the gcode into chunks that end on a new line and fit within
+/// the u16 input buffer.
+/// TODO: decide what is a reasonable size gcode chunk
+/// and check against the libgcode reference.
+pub(crate) fn gcode_chunks(gcode: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    for (i, b) in gcode.iter().enumerate() {
+        // If the chunk is nearing max u16 and
+        // we reach a new line then end it.
+        if *b == b'\n' && i + 1 - start > (u16::MAX - 100) as usize {
+            chunks.push(&gcode[start..=i]);
+            start = i + 1;
         }
     }
+    // One remaining chunk
+    if start < gcode.len() {
+        chunks.push(&gcode[start..]);
+    }
+    chunks
+}
+
+/// Serialise the gcode chunks into blocks in order.
+#[cfg(not(feature = "parallel"))]
+fn serialise_gcode_chunks(
+    chunks: &[&[u8]],
+    options: &AsciiToBinaryOptions,
+) -> Result<Vec<StatisticsBlock>, BinaryGcodeError> {
+    chunks
+        .iter()
+        .map(|chunk| {
+            options.serialise_block(
+                BlockKind::GCode,
+                options.gcode_compression,
+                Encoding::Ascii,
+                &[],
+                chunk,
+            )
+        })
+        .collect()
+}
 
-    Ok(binary.into_boxed_slice())
+/// Serialise the gcode chunks into blocks across threads. The blocks are
+/// returned in the same order as the chunks so the output is identical to
+/// the sequential version.
+#[cfg(feature = "parallel")]
+fn serialise_gcode_chunks(
+    chunks: &[&[u8]],
+    options: &AsciiToBinaryOptions,
+) -> Result<Vec<StatisticsBlock>, BinaryGcodeError> {
+    chunks
+        .par_iter()
+        .map(|chunk| {
+            options.serialise_block(
+                BlockKind::GCode,
+                options.gcode_compression,
+                Encoding::Ascii,
+                &[],
+                chunk,
+            )
+        })
+        .collect()
 }
 
-fn thumbnail_block(thumb: &str) -> Result<Box<[u8]>, BinaryGcodeError> {
+fn thumbnail_block(
+    thumb: &str,
+    options: &AsciiToBinaryOptions,
+) -> Result<StatisticsBlock, BinaryGcodeError> {
     // TODO: Add checks to the &str input
 
     let (left, right) = thumb.split_once(";").unwrap();
@@ -202,11 +433,6 @@ fn thumbnail_block(thumb: &str) -> Result<Box<[u8]>, BinaryGcodeError> {
     }
     let h = h.unwrap();
 
-    let mut parameters: Vec<u8> = Vec::new();
-    // parameters beyond the encoding
-    parameters.extend(w.to_le_bytes());
-    parameters.extend(h.to_le_bytes());
-
     let mut right = right.to_string();
     right = right.replace("\n; ", "");
     right = right.replace("thumbnail end", "");
@@ -217,19 +443,43 @@ fn thumbnail_block(thumb: &str) -> Result<Box<[u8]>, BinaryGcodeError> {
     }
     let data = data.unwrap();
 
-    serialise_block(
+    // The comment header must describe the image that follows it.
+    let thumb = Thumbnail {
+        format: ThumbnailFormat::from_encoding(encoding)?,
+        width: w,
+        height: h,
+        data: data.into_boxed_slice(),
+    };
+    thumb.validate()?;
+
+    let thumb = match options.thumbnail_format {
+        Some(format) if format != thumb.format => Thumbnail {
+            format,
+            data: thumb.decode()?.encode(format)?,
+            ..thumb
+        },
+        _ => thumb,
+    };
+
+    options.serialise_block(
         BlockKind::Thumbnail,
-        CompressionAlgorithm::None,
-        encoding,
-        Checksum::Crc32,
-        &parameters,
-        &data,
+        options.thumbnail_compression,
+        thumb.format.encoding(),
+        &thumb.additional_parameters(),
+        &thumb.data,
     )
 }
 
 #[cfg(test)]
 mod tests {
-    use super::thumbnail_block;
+    use super::{
+        ascii_to_binary, ascii_to_binary_with_options,
+        ascii_to_binary_with_statistics, binary_to_ascii, thumbnail_block,
+        AsciiToBinaryOptions,
+    };
+    use crate::components::common::BinaryGcodeError;
+    use crate::components::serialiser::CompressionLevel;
+    use alloc::vec::Vec;
 
     #[test]
     fn convert_thumbnail_block() {
@@ -244,6 +494,102 @@ mod tests {
 ; 92XVd4PQAPjuOgXC571aT4cJ3tgG8nzqx5gWzbFv5fUBP7TVgxxNgAAAAASUVORK5CYII=
 ; thumbnail end";
 
-        let _ = thumbnail_block(thumb).expect("Error making thumbnail");
+        let options = AsciiToBinaryOptions::default();
+        let _ =
+            thumbnail_block(thumb, &options).expect("Error making thumbnail");
+
+        // The header must match the image.
+        for header in ["thumbnail begin 16x20", "thumbnail_QOI begin 16x16"] {
+            let thumb = thumb.replace("thumbnail begin 16x16", header);
+            assert!(matches!(
+                thumbnail_block(&thumb, &options),
+                Err(BinaryGcodeError::ThumbnailMismatch { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn convert_with_options() {
+        let gcode = include_str!("../../test_files/mini_cube_ps2.8.1.gcode");
+        let default = ascii_to_binary(gcode).unwrap();
+        let options = AsciiToBinaryOptions {
+            level: CompressionLevel::Fast,
+            auto_compression: Some(Vec::new()),
+            ..Default::default()
+        };
+        let (auto, stats) =
+            ascii_to_binary_with_statistics(gcode, &options).unwrap();
+        assert!(auto.len() <= default.len());
+        // Every block is reported with the smallest of its candidates.
+        let blocks: usize = stats.iter().map(|s| s.block_len).sum();
+        assert_eq!(blocks + 10, auto.len());
+        for s in &stats {
+            assert_eq!(s.candidates.len(), 4);
+            assert!(s.candidates.iter().all(|&(_, len)| len >= s.block_len));
+        }
+        assert_eq!(
+            binary_to_ascii(&default, false).unwrap(),
+            binary_to_ascii(&auto, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn converted_files_verify() {
+        use crate::components::common::BlockKind;
+        use crate::components::metadata::get_metadata;
+        use crate::components::verify::verify;
+
+        for gcode in [
+            include_str!("../../test_files/mini_cube_b.gcode"),
+            include_str!("../../test_files/mini_cube_ps2.8.1.gcode"),
+        ] {
+            let binary = ascii_to_binary(gcode).unwrap();
+            let problems = verify(&binary);
+            assert!(problems.is_empty(), "{:?}", problems);
+            // The totals come from after the gcode, not the header.
+            let key = "estimated first layer printing time (normal mode)";
+            let kind = BlockKind::PrintMetadata;
+            assert!(get_metadata(&binary, kind, key).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn convert_regenerates_progress() {
+        use crate::components::time::MachineLimits;
+
+        let gcode = include_str!("../../test_files/mini_cube_ps2.8.1.gcode");
+        let options = AsciiToBinaryOptions {
+            regenerate_progress: Some(MachineLimits::default()),
+            ..Default::default()
+        };
+        let binary = ascii_to_binary_with_options(gcode, &options).unwrap();
+        let ascii = binary_to_ascii(&binary, false).unwrap();
+        // The slicer estimates 3m 41s.
+        assert!(ascii.contains("M73 P0 R3\n"));
+        assert!(ascii.contains("M73 P50 R1\n"));
+        assert!(ascii.contains("M73 P100 R0\n"));
+        // The silent mode lines are kept.
+        assert!(ascii.contains("M73 Q0 S3\n"));
+    }
+
+    #[cfg(feature = "images")]
+    #[test]
+    fn convert_transcodes_thumbnails() {
+        use crate::components::thumbnail::{thumbnails, ThumbnailFormat};
+
+        let gcode = include_str!("../../test_files/mini_cube_ps2.8.1.gcode");
+        let options = AsciiToBinaryOptions {
+            thumbnail_format: Some(ThumbnailFormat::Png),
+            ..Default::default()
+        };
+        let qoi = thumbnails(&ascii_to_binary(gcode).unwrap()).unwrap();
+        let binary = ascii_to_binary_with_options(gcode, &options).unwrap();
+        let png = thumbnails(&binary).unwrap();
+        assert_eq!(qoi.len(), png.len());
+        for (q, p) in qoi.iter().zip(png.iter()) {
+            assert_eq!(q.format, ThumbnailFormat::Qoi);
+            assert_eq!(p.format, ThumbnailFormat::Png);
+            assert_eq!(q.decode().unwrap(), p.decode().unwrap());
+        }
     }
 }
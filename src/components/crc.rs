@@ -0,0 +1,165 @@
+/// The reflected CRC32 (IEEE) polynomial used by the specification.
+const POLYNOMIAL: u32 = 0xEDB88320;
+
+/// The slicing-by-8 lookup tables. The first table is the classic
+/// byte-at-a-time table following
+/// [lxp32](https://lxp32.github.io/docs/a-simple-example-crc32-calculation/)s
+/// example and each subsequent table advances the crc of the previous
+/// one by another byte of zeros.
+static CRC32_TABLES: [[u32; 256]; 8] = crc32_tables();
+
+const fn crc32_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                POLYNOMIAL ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        tables[0][i] = crc;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 256 {
+        let mut slice = 1;
+        while slice < 8 {
+            let prev = tables[slice - 1][i];
+            tables[slice][i] = (prev >> 8) ^ tables[0][(prev & 0xFF) as usize];
+            slice += 1;
+        }
+        i += 1;
+    }
+    tables
+}
+
+/// A portable slicing-by-8 crc32 that processes 8 bytes per iteration
+/// and falls back to a byte-at-a-time lookup for the remainder.
+#[cfg_attr(feature = "std", allow(dead_code))]
+fn update_slice_by_8(
+    mut crc: u32,
+    buf: &[u8],
+) -> u32 {
+    let t = &CRC32_TABLES;
+    let mut chunks = buf.chunks_exact(8);
+    for c in &mut chunks {
+        let lo = u32::from_le_bytes([c[0], c[1], c[2], c[3]]) ^ crc;
+        let hi = u32::from_le_bytes([c[4], c[5], c[6], c[7]]);
+        crc = t[7][(lo & 0xFF) as usize]
+            ^ t[6][((lo >> 8) & 0xFF) as usize]
+            ^ t[5][((lo >> 16) & 0xFF) as usize]
+            ^ t[4][(lo >> 24) as usize]
+            ^ t[3][(hi & 0xFF) as usize]
+            ^ t[2][((hi >> 8) & 0xFF) as usize]
+            ^ t[1][((hi >> 16) & 0xFF) as usize]
+            ^ t[0][(hi >> 24) as usize];
+    }
+    for byte in chunks.remainder() {
+        let idx = (crc as u8 ^ byte) as usize;
+        crc = t[0][idx] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// An incremental crc32 hasher so a block can be checksummed as it is
+/// written without buffering it first. With the `std` feature the
+/// hashing is delegated to [crc32fast](https://crates.io/crates/crc32fast)
+/// which uses hardware acceleration when the cpu supports it.
+///
+/// ```
+/// use binarygcode::Crc32;
+///
+/// let mut hasher = Crc32::new();
+/// hasher.update(b"1234");
+/// hasher.update(b"56789");
+/// assert_eq!(hasher.finalize(), 0xCBF43926);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+    #[cfg(not(feature = "std"))]
+    state: u32,
+    #[cfg(feature = "std")]
+    hasher: crc32fast::Hasher,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    /// Create a new hasher.
+    pub fn new() -> Self {
+        Self {
+            #[cfg(not(feature = "std"))]
+            state: 0xFFFFFFFF,
+            #[cfg(feature = "std")]
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Feed some more bytes into the hasher.
+    pub fn update(
+        &mut self,
+        buf: &[u8],
+    ) {
+        #[cfg(not(feature = "std"))]
+        {
+            self.state = update_slice_by_8(self.state, buf);
+        }
+        #[cfg(feature = "std")]
+        self.hasher.update(buf);
+    }
+
+    /// Return the checksum of all the bytes provided so far.
+    pub fn finalize(&self) -> u32 {
+        #[cfg(not(feature = "std"))]
+        {
+            !self.state
+        }
+        #[cfg(feature = "std")]
+        self.hasher.clone().finalize()
+    }
+
+    /// Reset the hasher to its initial state.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{update_slice_by_8, Crc32, CRC32_TABLES};
+    use alloc::vec::Vec;
+
+    fn crc32_bytewise(buf: &[u8]) -> u32 {
+        let mut crc: u32 = 0xFFFFFFFF;
+        for byte in buf {
+            let idx = (crc as u8 ^ byte) as usize;
+            crc = CRC32_TABLES[0][idx] ^ (crc >> 8);
+        }
+        !crc
+    }
+
+    #[test]
+    fn crc32_matches_bytewise() {
+        let data: Vec<u8> = (0..1031u32).map(|i| (i * 31 + 7) as u8).collect();
+        // Cover every alignment of the remainder.
+        for len in 0..data.len() {
+            let buf = &data[..len];
+            let expected = crc32_bytewise(buf);
+            assert_eq!(!update_slice_by_8(0xFFFFFFFF, buf), expected);
+            let mut hasher = Crc32::new();
+            let (a, b) = buf.split_at(len / 3);
+            hasher.update(a);
+            hasher.update(b);
+            assert_eq!(hasher.finalize(), expected);
+        }
+    }
+}
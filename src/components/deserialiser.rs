@@ -1,4 +1,4 @@
-use core::{array::TryFromSliceError, fmt};
+use core::{array::TryFromSliceError, fmt, ops::Range};
 
 use alloc::{borrow::ToOwned, boxed::Box, vec::Vec};
 use base64::{prelude::BASE64_STANDARD, Engine};
@@ -12,6 +12,14 @@ use crate::components::common::{
     crc32, BinaryGcodeError, BlockKind, Checksum, CompressionAlgorithm,
     Encoding, MAGIC,
 };
+use crate::components::thumbnail::Thumbnail;
+
+/// The longest line in bytes meatpack can pack or unpack, as firmware
+/// unpacks into a fixed line buffer.
+pub(crate) const MEATPACK_LINE_LEN: usize = 256;
+
+/// A meatpack byte holding two line feeds.
+const MEATPACK_PADDING: u8 = 0xcc;
 
 /// A utility enum to keep track of the state of the deserialiser
 /// instance when digesting some bytes.
@@ -28,6 +36,10 @@ pub enum DeserialisedResult {
     Block(DeserialisedBlock),
 }
 
+/// The bytes read at a time by `Deserialiser::read_from`.
+#[cfg(feature = "std")]
+const READ_CHUNK_LEN: usize = 8192;
+
 /// A struct containing the header details of the bgcode.
 #[derive(Debug)]
 pub struct DeserialisedFileHeader {
@@ -51,8 +63,21 @@ pub(crate) fn try_from_slice<const N: usize>(
 /// digest data in chunks and returns header and blocks when available.
 /// The block remain compressed so the user can decide which ones they
 /// which to decompress.
+///
+/// Deserialised bytes are not removed from the buffer straight away.
+/// A read cursor is advanced instead and the consumed bytes are only
+/// discarded when more are digested and they make up at least half of the
+/// buffer. This keeps parsing an already complete buffer linear. The buffer
+/// is private as a result, use [`Deserialiser::remaining`] in place of the
+/// formerly public `inner` for the bytes not deserialised yet.
 pub struct Deserialiser {
-    pub inner: Vec<u8>,
+    /// The digested bytes. Those before the read cursor have already been
+    /// deserialised and are discarded by a later digest.
+    inner: Vec<u8>,
+    /// The position of the next unread byte in inner.
+    cursor: usize,
+    /// The position of inner's first byte in the digested stream.
+    offset: usize,
     state: DeserialiserState,
     checksum: Checksum,
 }
@@ -61,6 +86,8 @@ impl Default for Deserialiser {
     fn default() -> Self {
         Self {
             inner: Vec::new(),
+            cursor: 0,
+            offset: 0,
             state: DeserialiserState::FileHeader,
             checksum: Checksum::None,
         }
@@ -73,12 +100,53 @@ impl Deserialiser {
         &mut self,
         buf: &[u8],
     ) {
+        // Discard the consumed bytes once they dominate the buffer so the
+        // cost of moving the unread bytes is amortised.
+        if self.cursor > 0 && self.cursor >= self.inner.len() / 2 {
+            self.inner.drain(..self.cursor);
+            self.offset += self.cursor;
+            self.cursor = 0;
+        }
         self.inner.extend(buf);
     }
 
+    /// Read the next chunk of bytes from a reader and digest them,
+    /// returning how many were read. Zero is returned at the end of the
+    /// reader.
+    #[cfg(feature = "std")]
+    pub fn read_from(
+        &mut self,
+        reader: &mut impl std::io::Read,
+    ) -> std::io::Result<usize> {
+        let mut buf = [0u8; READ_CHUNK_LEN];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(read) => {
+                    self.digest(&buf[..read]);
+                    return Ok(read);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The digested bytes that have not been deserialised yet.
+    pub fn remaining(&self) -> &[u8] {
+        &self.inner[self.cursor..]
+    }
+
+    /// The position in the digested stream of the next byte to be
+    /// deserialised, i.e. the offset of the next block in the file.
+    pub fn position(&self) -> usize {
+        self.offset + self.cursor
+    }
+
     /// Reset the deserialisor to its default state.
     pub fn reset(&mut self) {
         self.inner.clear();
+        self.cursor = 0;
+        self.offset = 0;
         self.state = DeserialiserState::FileHeader;
     }
 
@@ -97,22 +165,21 @@ impl Deserialiser {
     fn deserialise_file_header(
         &mut self
     ) -> Result<DeserialisedResult, BinaryGcodeError> {
-        if self.inner.len() < 10 {
-            return Ok(DeserialisedResult::MoreBytesRequired(
-                10 - self.inner.len(),
-            ));
+        let buf = &self.inner[self.cursor..];
+        if buf.len() < 10 {
+            return Ok(DeserialisedResult::MoreBytesRequired(10 - buf.len()));
         }
         // We have enough data to read the file header
-        let bytes = try_from_slice::<4>(&self.inner[0..=3])?;
+        let bytes = try_from_slice::<4>(&buf[0..=3])?;
         let magic = u32::from_le_bytes(bytes);
         if magic != MAGIC {
             return Err(BinaryGcodeError::InvalidMagic(magic));
         }
 
-        let bytes = try_from_slice::<4>(&self.inner[4..=7])?;
+        let bytes = try_from_slice::<4>(&buf[4..=7])?;
         let version = u32::from_le_bytes(bytes);
 
-        let bytes = try_from_slice::<2>(&self.inner[8..=9])?;
+        let bytes = try_from_slice::<2>(&buf[8..=9])?;
         let checksum_value = u16::from_le_bytes(bytes);
 
         let checksum = match checksum_value {
@@ -124,12 +191,12 @@ impl Deserialiser {
         let fh = DeserialisedFileHeader {
             magic,
             version,
-            checksum: checksum.clone(),
+            checksum,
         };
 
         self.checksum = checksum;
         self.state = DeserialiserState::Block;
-        self.inner.drain(..10);
+        self.cursor += 10;
 
         Ok(DeserialisedResult::FileHeader(fh))
     }
@@ -138,110 +205,216 @@ impl Deserialiser {
     fn deserialise_block(
         &mut self
     ) -> Result<DeserialisedResult, BinaryGcodeError> {
-        // Check if we have enough data to read the block header
-        if self.inner.len() < 12 {
-            return Ok(DeserialisedResult::MoreBytesRequired(
-                12 - self.inner.len(),
-            ));
+        let layout = match frame_block(&self.inner, self.cursor, self.checksum)?
+        {
+            Framing::MoreBytesRequired(n) => {
+                return Ok(DeserialisedResult::MoreBytesRequired(n))
+            }
+            Framing::Block(layout) => layout,
+        };
+        match layout.checksum {
+            Some((stored, computed)) if stored != computed => {
+                return Err(BinaryGcodeError::InvalidChecksum(
+                    stored, computed,
+                ));
+            }
+            _ => {}
         }
+        let b = layout.block(&self.inner)?;
+        self.cursor = layout.range.end;
+        Ok(DeserialisedResult::Block(b))
+    }
+}
 
-        // Check the header
-        let bytes = try_from_slice::<2>(&self.inner[0..=1])?;
-        let kind = BlockKind::from_le_bytes(bytes)?;
+/// Where the parts of a block are in a file, read from its header without
+/// checking its checksum or decoding its parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockLayout {
+    pub kind: BlockKind,
+    pub compression: CompressionAlgorithm,
+    pub data_uncompressed_len: usize,
+    pub data_compressed_len: Option<usize>,
+    /// The whole block from its header to its checksum.
+    pub range: Range<usize>,
+    pub parameters: Range<usize>,
+    pub data: Range<usize>,
+    /// The checksum stored with the block and the one computed over it.
+    pub checksum: Option<(u32, u32)>,
+}
 
-        let bytes = try_from_slice::<2>(&self.inner[2..=3])?;
-        let compression = CompressionAlgorithm::from_le_bytes(bytes)?;
+impl BlockLayout {
+    /// Read the layout of the block at an offset in a complete file.
+    pub fn read(
+        binary: &[u8],
+        offset: usize,
+        checksum: Checksum,
+    ) -> Result<Self, BinaryGcodeError> {
+        match frame_block(binary, offset.min(binary.len()), checksum)? {
+            Framing::MoreBytesRequired(n) => {
+                Err(BinaryGcodeError::UnexpectedEof(n))
+            }
+            Framing::Block(layout) => Ok(layout),
+        }
+    }
 
-        let bytes = try_from_slice::<4>(&self.inner[4..=7])?;
-        let data_uncompressed_len = u32::from_le_bytes(bytes) as usize;
+    /// Copy the block out of the file it was read from, decoding its
+    /// encoding.
+    pub fn block(
+        &self,
+        binary: &[u8],
+    ) -> Result<DeserialisedBlock, BinaryGcodeError> {
+        let parameters = &binary[self.parameters.clone()];
+        let encoding = try_from_slice::<2>(&parameters[..2])?;
+        Ok(DeserialisedBlock {
+            kind: self.kind,
+            data_compressed_len: self.data_compressed_len,
+            data_uncompressed_len: self.data_uncompressed_len,
+            compression: self.compression,
+            encoding: Encoding::from_le_bytes(encoding, &self.kind)?,
+            parameters: parameters.to_owned().into_boxed_slice(),
+            data: binary[self.data.clone()].to_owned().into_boxed_slice(),
+        })
+    }
+}
 
-        let data_compressed_len: Option<usize> = match compression {
-            CompressionAlgorithm::None => None,
-            _ => {
-                let bytes = try_from_slice::<4>(&self.inner[8..=11])?;
-                Some(u32::from_le_bytes(bytes) as usize)
-            }
-        };
+/// The outcome of finding the parts of a block in a buffer.
+enum Framing {
+    MoreBytesRequired(usize),
+    Block(BlockLayout),
+}
 
-        let param_len = match kind {
-            BlockKind::Thumbnail => 6,
-            _ => 2,
-        };
+/// Find the parts of the block starting at an offset in a buffer. The
+/// compressed length is only present for compressed blocks.
+fn frame_block(
+    buf: &[u8],
+    start: usize,
+    checksum: Checksum,
+) -> Result<Framing, BinaryGcodeError> {
+    let header = &buf[start..];
+    if header.len() < 8 {
+        return Ok(Framing::MoreBytesRequired(8 - header.len()));
+    }
 
-        // Have we collected all the data we need?
-        let block_len = match data_compressed_len {
-            Some(compressed_len) => {
-                // header + parameters + comrpessed_len
-                let mut block_len = 12 + param_len + compressed_len;
-                if self.checksum == Checksum::Crc32 {
-                    block_len += 4;
-                }
-                if self.inner.len() < block_len {
-                    return Ok(DeserialisedResult::MoreBytesRequired(
-                        block_len - self.inner.len(),
-                    ));
-                }
-                block_len
+    let bytes = try_from_slice::<2>(&header[0..=1])?;
+    let kind = BlockKind::from_le_bytes(bytes)?;
+
+    let bytes = try_from_slice::<2>(&header[2..=3])?;
+    let compression = CompressionAlgorithm::from_le_bytes(bytes)?;
+
+    let bytes = try_from_slice::<4>(&header[4..=7])?;
+    let data_uncompressed_len = u32::from_le_bytes(bytes) as usize;
+
+    let data_compressed_len: Option<usize> = match compression {
+        CompressionAlgorithm::None => None,
+        _ if header.len() < 12 => {
+            return Ok(Framing::MoreBytesRequired(12 - header.len()));
+        }
+        _ => {
+            let bytes = try_from_slice::<4>(&header[8..=11])?;
+            Some(u32::from_le_bytes(bytes) as usize)
+        }
+    };
+
+    let param_start = match data_compressed_len {
+        Some(_) => start + 12,
+        None => start + 8,
+    };
+    let data_start = param_start + kind.parameter_byte_size();
+    let data_end =
+        data_start + data_compressed_len.unwrap_or(data_uncompressed_len);
+    let end = data_end + checksum.checksum_byte_size();
+    if buf.len() < end {
+        return Ok(Framing::MoreBytesRequired(end - buf.len()));
+    }
+
+    let checksum = match checksum {
+        Checksum::None => None,
+        Checksum::Crc32 => {
+            let bytes = try_from_slice::<4>(&buf[data_end..end])?;
+            Some((u32::from_le_bytes(bytes), crc32(&buf[start..data_end])))
+        }
+    };
+
+    Ok(Framing::Block(BlockLayout {
+        kind,
+        compression,
+        data_uncompressed_len,
+        data_compressed_len,
+        range: start..end,
+        parameters: param_start..data_start,
+        data: data_start..data_end,
+        checksum,
+    }))
+}
+
+/// A deserialised block along with the range of bytes it occupies.
+pub struct FramedBlock {
+    pub range: Range<usize>,
+    pub block: DeserialisedBlock,
+}
+
+/// Deserialise a complete binary gcode file returning its header and
+/// every block along with where it is in the file.
+pub fn deserialise_file(
+    binary: &[u8]
+) -> Result<(DeserialisedFileHeader, Vec<FramedBlock>), BinaryGcodeError> {
+    let mut deserialiser = Deserialiser::default();
+    deserialiser.digest(binary);
+    let mut header = None;
+    let mut blocks = Vec::new();
+    loop {
+        let start = deserialiser.position();
+        match deserialiser.deserialise()? {
+            DeserialisedResult::FileHeader(fh) => header = Some(fh),
+            DeserialisedResult::Block(block) => {
+                let range = start..deserialiser.position();
+                blocks.push(FramedBlock { range, block });
             }
-            None => {
-                let mut block_len = 8 + param_len + data_uncompressed_len;
-                if self.checksum == Checksum::Crc32 {
-                    block_len += 4;
-                }
-                if self.inner.len() < block_len {
-                    return Ok(DeserialisedResult::MoreBytesRequired(
-                        block_len - self.inner.len(),
-                    ));
+            DeserialisedResult::MoreBytesRequired(n) => {
+                if header.is_none() || !deserialiser.remaining().is_empty() {
+                    return Err(BinaryGcodeError::UnexpectedEof(n));
                 }
-                block_len
+                break;
             }
-        };
+        }
+    }
+    // Checked above.
+    let header = header.unwrap();
+    Ok((header, blocks))
+}
 
-        // Checksum check
-        match self.checksum {
-            Checksum::None => {}
-            Checksum::Crc32 => {
-                let bytes =
-                    try_from_slice::<4>(&self.inner[block_len - 4..block_len])?;
-                let c = u32::from_le_bytes(bytes);
-                let chk = crc32(&self.inner[..block_len - 4]);
-                if c != chk {
-                    return Err(BinaryGcodeError::InvalidChecksum(c, chk));
+/// Pumps the ascii gcode of a decompressed gcode block into a buffer.
+pub(crate) fn unpack_gcode(
+    encoding: Encoding,
+    data: &[u8],
+    buf: &mut Vec<u8>,
+) -> Result<(), BinaryGcodeError> {
+    match encoding {
+        Encoding::Ascii => buf.extend(data),
+        // Use the Meatpack crate to re-encode back to ASCII Gcode.
+        Encoding::Meatpack | Encoding::MeatpackWithComments => {
+            let start = buf.len();
+            let mut unpacker = Unpacker::default();
+            let mut rest = data;
+            while let Some(i) = rest.iter().position(|&b| b == MEATPACK_PADDING)
+            {
+                let mut reader = &rest[..i];
+                unpacker.unpack(&mut reader, buf)?;
+                // Slicers pad the end of a line to a whole byte with a packed
+                // pair of line feeds, which holds no gcode when it starts a
+                // line.
+                if buf.len() > start && buf.last() != Some(&b'\n') {
+                    let mut reader = &rest[i..=i];
+                    unpacker.unpack(&mut reader, buf)?;
                 }
+                rest = &rest[i + 1..];
             }
+            let mut reader = rest;
+            unpacker.unpack(&mut reader, buf)?;
         }
-
-        let param_start = match data_compressed_len {
-            Some(_) => 12,
-            None => 8,
-        };
-
-        let encoding = &self.inner[param_start..param_start + 2];
-        let encoding = try_from_slice::<2>(encoding)?;
-        let encoding = Encoding::from_le_bytes(encoding, &kind)?;
-
-        let parameters = self.inner[param_start..param_start + param_len]
-            .to_owned()
-            .into_boxed_slice();
-        let data = self.inner[param_start + param_len..block_len - 4]
-            .to_owned()
-            .into_boxed_slice();
-
-        // Pass out the block
-        let b = DeserialisedBlock {
-            kind,
-            data_compressed_len,
-            data_uncompressed_len,
-            compression,
-            encoding,
-            parameters,
-            data,
-        };
-
-        self.inner.drain(..block_len);
-
-        Ok(DeserialisedResult::Block(b))
+        _ => {}
     }
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -249,6 +422,14 @@ pub enum BlockError {
     DecodeError(&'static str),
 }
 
+impl From<BlockError> for BinaryGcodeError {
+    fn from(e: BlockError) -> Self {
+        match e {
+            BlockError::DecodeError(e) => BinaryGcodeError::DecodeError(e),
+        }
+    }
+}
+
 /// A struct representing a deserialised binary gcode block.
 #[derive(Debug)]
 pub struct DeserialisedBlock {
@@ -296,13 +477,38 @@ impl DeserialisedBlock {
         }
     }
 
+    /// Return the ascii gcode of a gcode block, decompressing it and
+    /// unpacking any meatpack encoding.
+    pub fn gcode(&self) -> Result<Vec<u8>, BinaryGcodeError> {
+        if self.kind != BlockKind::GCode {
+            return Err(BinaryGcodeError::DecodeError("not_gcode"));
+        }
+        let data = self.decompress()?;
+        let mut buf = Vec::with_capacity(self.data_uncompressed_len);
+        unpack_gcode(self.encoding, &data, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Write the decompressed ascii representation of the block to a
+    /// writer, as with `to_ascii`.
+    #[cfg(feature = "std")]
+    pub fn write_ascii(
+        &mut self,
+        writer: &mut impl std::io::Write,
+        with_block_comments: bool,
+    ) -> std::io::Result<()> {
+        let mut buf = Vec::new();
+        self.to_ascii(&mut buf, with_block_comments)?;
+        writer.write_all(&buf)
+    }
+
     /// Pumps the decompressed ascii representation of the gcode block into a buffer. The user can define whether they want to include our block comments so they can see the decomposition of blocks in the gcode.
     pub fn to_ascii(
         &mut self,
         buf: &mut Vec<u8>,
         with_block_comments: bool,
     ) -> Result<(), BinaryGcodeError> {
-        let data = self.decompress().unwrap();
+        let data = self.decompress()?;
         match self.kind {
             BlockKind::FileMetadata => {
                 if with_block_comments {
@@ -342,16 +548,13 @@ impl DeserialisedBlock {
                 }
             }
             BlockKind::Thumbnail => {
-                //buf.resize_with(buf.len() + data.len(), Default::default);
-                let width = try_from_slice::<2>(&self.parameters[2..=3])?;
-                let width = u16::from_le_bytes(width);
-                let height = try_from_slice::<2>(&self.parameters[4..=5])?;
-                let height = u16::from_le_bytes(height);
+                let thumbnail = Thumbnail::from_data(self, data)?;
+                let (width, height) = (thumbnail.width, thumbnail.height);
 
                 if with_block_comments {
                     buf.extend("; [THUMBNAIL_BLOCK_START]\n".as_bytes());
                 }
-                let r = BASE64_STANDARD.encode(&data).into_bytes();
+                let r = BASE64_STANDARD.encode(&thumbnail.data).into_bytes();
                 match self.encoding {
                     Encoding::Png => {
                         let header = format!(
@@ -408,25 +611,7 @@ impl DeserialisedBlock {
                 if with_block_comments {
                     buf.extend("; [GCODE_BLOCK_START]\n".as_bytes());
                 }
-                match self.encoding {
-                    Encoding::Ascii => buf.extend(data),
-                    Encoding::Meatpack => {
-                        // Use the Meatpack crate to re-encode back to ASCII Gcode.
-                        if let Some(e) =
-                            Unpacker::<64>::unpack_slice(&data, buf).err()
-                        {
-                            return Err(BinaryGcodeError::Meatpack(e));
-                        }
-                    }
-                    Encoding::MeatpackWithComments => {
-                        if let Some(e) =
-                            Unpacker::<64>::unpack_slice(&data, buf).err()
-                        {
-                            return Err(BinaryGcodeError::Meatpack(e));
-                        }
-                    }
-                    _ => {}
-                }
+                unpack_gcode(self.encoding, &data, buf)?;
                 if with_block_comments {
                     buf.extend("; [GCODE_BLOCK_END]\n".as_bytes());
                 }
@@ -0,0 +1,600 @@
+//! A `no_std` tokenizer that turns ascii gcode into typed commands while
+//! keeping the span of every line and word so the original text can be
+//! re-emitted byte for byte.
+
+use core::{fmt, ops::Range, str};
+
+/// A letter followed by a value such as `X10.5` or `G1`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Word<'a> {
+    /// The letter in upper case.
+    pub letter: u8,
+    /// The value exactly as written.
+    pub value: &'a [u8],
+    /// The byte range of the word in the input.
+    pub span: Range<usize>,
+}
+
+impl Word<'_> {
+    /// Parse the value as a number.
+    pub fn number(&self) -> Option<f32> {
+        str::from_utf8(self.value).ok()?.parse().ok()
+    }
+
+    /// Parse the value as a whole number code such as the `1` in `G1`.
+    pub fn code(&self) -> Option<u16> {
+        str::from_utf8(self.value).ok()?.parse().ok()
+    }
+}
+
+/// Iterates over the words of the command part of a line. Words do not
+/// need to be separated by whitespace as meatpack removes it.
+#[derive(Debug, Clone)]
+pub struct Words<'a> {
+    input: &'a [u8],
+    /// The offset of the input from the start of the gcode.
+    offset: usize,
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = Word<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.input.iter().position(|b| !b.is_ascii_whitespace())?;
+        let rest = &self.input[start + 1..];
+        let len = rest
+            .iter()
+            .position(|b| {
+                b.is_ascii_alphabetic() || b.is_ascii_whitespace() || *b == b'*'
+            })
+            .unwrap_or(rest.len());
+        let span = self.offset + start..self.offset + start + 1 + len;
+        let word = Word {
+            letter: self.input[start].to_ascii_uppercase(),
+            value: &rest[..len],
+            span,
+        };
+        self.offset += start + 1 + len;
+        self.input = &rest[len..];
+        Some(word)
+    }
+}
+
+/// The axes of a command where each is optional.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Axes {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub z: Option<f32>,
+    pub e: Option<f32>,
+}
+
+/// A linear move.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Move {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub z: Option<f32>,
+    pub e: Option<f32>,
+    pub f: Option<f32>,
+}
+
+/// An arc move around a centre offset by `i` and `j` or with radius `r`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Arc {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub z: Option<f32>,
+    pub e: Option<f32>,
+    pub f: Option<f32>,
+    pub i: Option<f32>,
+    pub j: Option<f32>,
+    pub r: Option<f32>,
+}
+
+/// A gcode command. Values that are missing or fail to parse are `None`
+/// and commands without a typed variant are kept as their first word.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command<'a> {
+    /// Rapid move.
+    G0(Move),
+    /// Linear move.
+    G1(Move),
+    /// Clockwise arc.
+    G2(Arc),
+    /// Counter clockwise arc.
+    G3(Arc),
+    /// Dwell for `p` milliseconds or `s` seconds.
+    G4 { p: Option<f32>, s: Option<f32> },
+    /// Home the given axes, or all of them if none are given.
+    G28 { x: bool, y: bool, z: bool },
+    /// Absolute positioning.
+    G90,
+    /// Relative positioning.
+    G91,
+    /// Set the current position.
+    G92(Axes),
+    /// Absolute extrusion.
+    M82,
+    /// Relative extrusion.
+    M83,
+    /// Set the print progress in percent and the remaining minutes.
+    M73 { p: Option<f32>, r: Option<f32> },
+    /// Set the hotend temperature.
+    M104 { s: Option<f32>, t: Option<f32> },
+    /// Set the fan speed.
+    M106 { p: Option<f32>, s: Option<f32> },
+    /// Turn the fan off.
+    M107 { p: Option<f32> },
+    /// Wait for the hotend temperature.
+    M109 {
+        s: Option<f32>,
+        r: Option<f32>,
+        t: Option<f32>,
+    },
+    /// Set the bed temperature.
+    M140 { s: Option<f32> },
+    /// Wait for the bed temperature.
+    M190 { s: Option<f32>, r: Option<f32> },
+    /// Set the maximum accelerations in mm/s².
+    M201(Axes),
+    /// Set the maximum feedrates in mm/s.
+    M203(Axes),
+    /// Set the print, retract and travel accelerations in mm/s².
+    M204 {
+        p: Option<f32>,
+        r: Option<f32>,
+        t: Option<f32>,
+        s: Option<f32>,
+    },
+    /// Set the jerk of each axis, the minimum print and travel feedrates
+    /// and the junction deviation.
+    M205 {
+        x: Option<f32>,
+        y: Option<f32>,
+        z: Option<f32>,
+        e: Option<f32>,
+        s: Option<f32>,
+        t: Option<f32>,
+        j: Option<f32>,
+    },
+    /// Set the feedrate override in percent.
+    M220 { s: Option<f32> },
+    /// Set the flow override in percent.
+    M221 { s: Option<f32>, t: Option<f32> },
+    /// Select a tool.
+    T(u16),
+    /// Any other command. Its parameters are available from
+    /// [`GcodeLine::words`].
+    Other(Word<'a>),
+}
+
+impl<'a> Command<'a> {
+    /// Build a command from its first word and the words that follow.
+    fn parse(
+        word: Word<'a>,
+        params: Words<'a>,
+    ) -> Self {
+        // Returns the value of each parameter letter.
+        let get = |letter: u8| {
+            params
+                .clone()
+                .find(|w| w.letter == letter)
+                .and_then(|w| w.number())
+        };
+        let has = |letter: u8| params.clone().any(|w| w.letter == letter);
+        let axes = || Axes {
+            x: get(b'X'),
+            y: get(b'Y'),
+            z: get(b'Z'),
+            e: get(b'E'),
+        };
+        let linear = || Move {
+            x: get(b'X'),
+            y: get(b'Y'),
+            z: get(b'Z'),
+            e: get(b'E'),
+            f: get(b'F'),
+        };
+        let arc = || Arc {
+            x: get(b'X'),
+            y: get(b'Y'),
+            z: get(b'Z'),
+            e: get(b'E'),
+            f: get(b'F'),
+            i: get(b'I'),
+            j: get(b'J'),
+            r: get(b'R'),
+        };
+        let Some(code) = word.code() else {
+            return Command::Other(word);
+        };
+        match (word.letter, code) {
+            (b'G', 0) => Command::G0(linear()),
+            (b'G', 1) => Command::G1(linear()),
+            (b'G', 2) => Command::G2(arc()),
+            (b'G', 3) => Command::G3(arc()),
+            (b'G', 4) => Command::G4 {
+                p: get(b'P'),
+                s: get(b'S'),
+            },
+            (b'G', 28) => Command::G28 {
+                x: has(b'X'),
+                y: has(b'Y'),
+                z: has(b'Z'),
+            },
+            (b'G', 90) => Command::G90,
+            (b'G', 91) => Command::G91,
+            (b'G', 92) => Command::G92(axes()),
+            (b'M', 82) => Command::M82,
+            (b'M', 83) => Command::M83,
+            (b'M', 73) => Command::M73 {
+                p: get(b'P'),
+                r: get(b'R'),
+            },
+            (b'M', 104) => Command::M104 {
+                s: get(b'S'),
+                t: get(b'T'),
+            },
+            (b'M', 106) => Command::M106 {
+                p: get(b'P'),
+                s: get(b'S'),
+            },
+            (b'M', 107) => Command::M107 { p: get(b'P') },
+            (b'M', 109) => Command::M109 {
+                s: get(b'S'),
+                r: get(b'R'),
+                t: get(b'T'),
+            },
+            (b'M', 140) => Command::M140 { s: get(b'S') },
+            (b'M', 190) => Command::M190 {
+                s: get(b'S'),
+                r: get(b'R'),
+            },
+            (b'M', 201) => Command::M201(axes()),
+            (b'M', 203) => Command::M203(axes()),
+            (b'M', 204) => Command::M204 {
+                p: get(b'P'),
+                r: get(b'R'),
+                t: get(b'T'),
+                s: get(b'S'),
+            },
+            (b'M', 205) => Command::M205 {
+                x: get(b'X'),
+                y: get(b'Y'),
+                z: get(b'Z'),
+                e: get(b'E'),
+                s: get(b'S'),
+                t: get(b'T'),
+                j: get(b'J'),
+            },
+            (b'M', 220) => Command::M220 { s: get(b'S') },
+            (b'M', 221) => Command::M221 {
+                s: get(b'S'),
+                t: get(b'T'),
+            },
+            (b'T', tool) => Command::T(tool),
+            _ => Command::Other(word),
+        }
+    }
+}
+
+/// Write each parameter that is present as a space and its letter
+/// followed by its value.
+fn write_params(
+    f: &mut fmt::Formatter<'_>,
+    params: &[(char, Option<f32>)],
+) -> fmt::Result {
+    for (letter, value) in params {
+        if let Some(v) = value {
+            write!(f, " {}{}", letter, v)?;
+        }
+    }
+    Ok(())
+}
+
+/// Formats the command as gcode, for example `G1 X10 Y5.5 E0.25`.
+impl fmt::Display for Command<'_> {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            Command::G0(m) | Command::G1(m) => {
+                let code = if matches!(self, Command::G0(_)) { 0 } else { 1 };
+                write!(f, "G{}", code)?;
+                write_params(
+                    f,
+                    &[
+                        ('X', m.x),
+                        ('Y', m.y),
+                        ('Z', m.z),
+                        ('E', m.e),
+                        ('F', m.f),
+                    ],
+                )
+            }
+            Command::G2(a) | Command::G3(a) => {
+                let code = if matches!(self, Command::G2(_)) { 2 } else { 3 };
+                write!(f, "G{}", code)?;
+                write_params(
+                    f,
+                    &[
+                        ('X', a.x),
+                        ('Y', a.y),
+                        ('Z', a.z),
+                        ('I', a.i),
+                        ('J', a.j),
+                        ('R', a.r),
+                        ('E', a.e),
+                        ('F', a.f),
+                    ],
+                )
+            }
+            Command::G4 { p, s } => {
+                f.write_str("G4")?;
+                write_params(f, &[('P', *p), ('S', *s)])
+            }
+            Command::G28 { x, y, z } => {
+                f.write_str("G28")?;
+                // Homing flags are written without a value.
+                for (letter, set) in [('X', *x), ('Y', *y), ('Z', *z)] {
+                    if set {
+                        write!(f, " {}", letter)?;
+                    }
+                }
+                Ok(())
+            }
+            Command::G90 => f.write_str("G90"),
+            Command::G91 => f.write_str("G91"),
+            Command::G92(a) | Command::M201(a) | Command::M203(a) => {
+                let name = match self {
+                    Command::G92(_) => "G92",
+                    Command::M201(_) => "M201",
+                    _ => "M203",
+                };
+                f.write_str(name)?;
+                write_params(
+                    f,
+                    &[('X', a.x), ('Y', a.y), ('Z', a.z), ('E', a.e)],
+                )
+            }
+            Command::M82 => f.write_str("M82"),
+            Command::M83 => f.write_str("M83"),
+            Command::M73 { p, r } => {
+                f.write_str("M73")?;
+                write_params(f, &[('P', *p), ('R', *r)])
+            }
+            Command::M104 { s, t } => {
+                f.write_str("M104")?;
+                write_params(f, &[('S', *s), ('T', *t)])
+            }
+            Command::M106 { p, s } => {
+                f.write_str("M106")?;
+                write_params(f, &[('P', *p), ('S', *s)])
+            }
+            Command::M107 { p } => {
+                f.write_str("M107")?;
+                write_params(f, &[('P', *p)])
+            }
+            Command::M109 { s, r, t } => {
+                f.write_str("M109")?;
+                write_params(f, &[('S', *s), ('R', *r), ('T', *t)])
+            }
+            Command::M140 { s } => {
+                f.write_str("M140")?;
+                write_params(f, &[('S', *s)])
+            }
+            Command::M190 { s, r } => {
+                f.write_str("M190")?;
+                write_params(f, &[('S', *s), ('R', *r)])
+            }
+            Command::M204 { p, r, t, s } => {
+                f.write_str("M204")?;
+                write_params(f, &[('P', *p), ('R', *r), ('T', *t), ('S', *s)])
+            }
+            Command::M205 {
+                x,
+                y,
+                z,
+                e,
+                s,
+                t,
+                j,
+            } => {
+                f.write_str("M205")?;
+                write_params(
+                    f,
+                    &[
+                        ('X', *x),
+                        ('Y', *y),
+                        ('Z', *z),
+                        ('E', *e),
+                        ('S', *s),
+                        ('T', *t),
+                        ('J', *j),
+                    ],
+                )
+            }
+            Command::M220 { s } => {
+                f.write_str("M220")?;
+                write_params(f, &[('S', *s)])
+            }
+            Command::M221 { s, t } => {
+                f.write_str("M221")?;
+                write_params(f, &[('S', *s), ('T', *t)])
+            }
+            Command::T(tool) => write!(f, "T{}", tool),
+            Command::Other(word) => {
+                let value =
+                    str::from_utf8(word.value).map_err(|_| fmt::Error)?;
+                write!(f, "{}{}", word.letter as char, value)
+            }
+        }
+    }
+}
+
+/// A line of gcode with its command and comment.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GcodeLine<'a> {
+    /// The line exactly as written including its line ending.
+    pub raw: &'a [u8],
+    /// The byte range of the line in the input including its line ending.
+    pub span: Range<usize>,
+    /// The command if the line has one.
+    pub command: Option<Command<'a>>,
+    /// The text after the `;` if the line has a comment.
+    pub comment: Option<&'a [u8]>,
+}
+
+impl<'a> GcodeLine<'a> {
+    /// Return the line without its line ending.
+    pub fn text(&self) -> &'a [u8] {
+        let raw = self.raw.strip_suffix(b"\n").unwrap_or(self.raw);
+        raw.strip_suffix(b"\r").unwrap_or(raw)
+    }
+
+    /// Return every word before the comment including the command and
+    /// any line number or checksum.
+    pub fn words(&self) -> Words<'a> {
+        let text = self.text();
+        let end = text.iter().position(|b| *b == b';').unwrap_or(text.len());
+        Words {
+            input: &text[..end],
+            offset: self.span.start,
+        }
+    }
+
+    /// Return the comment as a string with surrounding whitespace removed.
+    pub fn comment_str(&self) -> Option<&'a str> {
+        str::from_utf8(self.comment?).ok().map(str::trim)
+    }
+}
+
+/// Iterates over the lines of some ascii gcode.
+///
+/// ```
+/// use binarygcode::{parse_gcode, Command};
+///
+/// let gcode = b"G1 X10 E0.5 ; wall\nM104 S215\n";
+/// let lines: Vec<_> = parse_gcode(gcode).collect();
+/// match &lines[0].command {
+///     Some(Command::G1(m)) => assert_eq!((m.x, m.e), (Some(10.0), Some(0.5))),
+///     _ => unreachable!(),
+/// }
+/// assert_eq!(lines[0].comment_str(), Some("wall"));
+/// assert_eq!(lines[1].command.as_ref().unwrap().to_string(), "M104 S215");
+/// ```
+#[derive(Debug, Clone)]
+pub struct GcodeLines<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+/// Parse some ascii gcode, such as that of [`DeserialisedBlock::gcode`](crate::DeserialisedBlock::gcode),
+/// into lines. Concatenating the raw lines reproduces the input.
+pub fn parse_gcode(input: &[u8]) -> GcodeLines<'_> {
+    GcodeLines { input, position: 0 }
+}
+
+impl<'a> Iterator for GcodeLines<'a> {
+    type Item = GcodeLine<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.input[self.position..];
+        if rest.is_empty() {
+            return None;
+        }
+        let len = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        let start = self.position;
+        self.position += len;
+
+        let mut line = GcodeLine {
+            raw: &rest[..len],
+            span: start..start + len,
+            command: None,
+            comment: None,
+        };
+        let text = line.text();
+        line.comment =
+            text.iter().position(|b| *b == b';').map(|i| &text[i + 1..]);
+        // Line numbers come before the command.
+        let mut words = line.words();
+        let word = loop {
+            match words.next() {
+                Some(w) if w.letter == b'N' => continue,
+                w => break w,
+            }
+        };
+        line.command = word.map(|w| Command::parse(w, words));
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_gcode, Command, Move};
+    use alloc::{string::ToString, vec::Vec};
+
+    #[test]
+    fn parse_gcode_losslessly() {
+        let gcode = include_bytes!("../../test_files/mini_cube_b.gcode");
+        let lines: Vec<_> = parse_gcode(gcode).collect();
+        let raw: Vec<u8> = lines.iter().flat_map(|l| l.raw).copied().collect();
+        assert_eq!(raw, gcode);
+        for line in &lines {
+            assert_eq!(&gcode[line.span.clone()], line.raw);
+            for word in line.words() {
+                assert_eq!(gcode[word.span.start], word.letter);
+            }
+        }
+        let moves = lines
+            .iter()
+            .filter(|l| matches!(l.command, Some(Command::G1(_))))
+            .count();
+        assert!(moves > 20000);
+    }
+
+    #[test]
+    fn parse_commands() {
+        let gcode = b"N10 G1X1.5y-2E.25F1200*45\r\n;LAYER_CHANGE\nM104 S215 T0\nM862.3 P \"MINI\"\nT1\n\nG28 X Y";
+        let lines: Vec<_> = parse_gcode(gcode).collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[0].command,
+            Some(Command::G1(Move {
+                x: Some(1.5),
+                y: Some(-2.0),
+                z: None,
+                e: Some(0.25),
+                f: Some(1200.0),
+            }))
+        );
+        assert_eq!(lines[0].text(), b"N10 G1X1.5y-2E.25F1200*45");
+        assert_eq!(lines[1].command, None);
+        assert_eq!(lines[1].comment_str(), Some("LAYER_CHANGE"));
+        assert_eq!(
+            lines[2].command,
+            Some(Command::M104 {
+                s: Some(215.0),
+                t: Some(0.0)
+            })
+        );
+        match &lines[3].command {
+            Some(Command::Other(word)) => {
+                assert_eq!((word.letter, word.value), (b'M', &b"862.3"[..]))
+            }
+            c => panic!("{:?}", c),
+        }
+        assert_eq!(lines[4].command, Some(Command::T(1)));
+        assert_eq!(lines[5].command, None);
+        assert_eq!(lines[6].command.as_ref().unwrap().to_string(), "G28 X Y");
+        assert_eq!(
+            lines[0].command.as_ref().unwrap().to_string(),
+            "G1 X1.5 Y-2 E0.25 F1200"
+        );
+    }
+}
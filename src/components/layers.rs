@@ -0,0 +1,339 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt::Write;
+
+use crate::components::common::{BinaryGcodeError, BlockKind};
+use crate::components::deserialiser::deserialise_file;
+use crate::components::gcode::{parse_gcode, Command, GcodeLine};
+use crate::components::metadata::{get_metadata, set_metadata};
+use crate::components::stats::move_target;
+
+/// The print metadata key a layer index is stored under.
+pub const LAYER_INDEX_KEY: &str = "layer_index";
+
+/// Where a layer starts in a binary gcode file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Layer {
+    /// The height of the layer.
+    pub z: f32,
+    /// The index of the block the layer starts in, counting every block
+    /// after the file header.
+    pub block_index: usize,
+    /// The byte offset of that block in the file.
+    pub block_offset: usize,
+    /// The byte offset of the start of the layer in the ascii gcode of
+    /// the block.
+    pub gcode_offset: usize,
+}
+
+/// The start of every layer in a binary gcode file.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LayerIndex {
+    pub layers: Vec<Layer>,
+}
+
+/// Finds layer changes in gcode. Layer change comments are preferred,
+/// then `;Z:` comments and finally moves that extrude at a new height.
+pub(crate) struct LayerDetector {
+    /// Starts of `;LAYER_CHANGE` or `;LAYER:` comments and the height
+    /// once it is known.
+    markers: Vec<(Option<f32>, usize)>,
+    z_comments: Vec<(f32, usize)>,
+    z_moves: Vec<(f32, usize)>,
+    position: [f64; 4],
+    absolute: bool,
+    absolute_e: bool,
+    /// The height and position of the last move that changed height.
+    z_move: (f32, usize),
+}
+
+impl Default for LayerDetector {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl LayerDetector {
+    /// Create a detector for gcode whose extrusion starts out absolute
+    /// unless `relative_e` is set.
+    pub(crate) fn new(relative_e: bool) -> Self {
+        Self {
+            markers: Vec::new(),
+            z_comments: Vec::new(),
+            z_moves: Vec::new(),
+            position: [0.0; 4],
+            absolute: true,
+            absolute_e: !relative_e,
+            z_move: (0.0, 0),
+        }
+    }
+
+    /// Feed the next line along with a position that identifies it in the
+    /// layers returned by [`LayerDetector::finish`].
+    pub(crate) fn line(
+        &mut self,
+        line: &GcodeLine,
+        position: usize,
+    ) {
+        if let Some(comment) = line.comment_str() {
+            if comment == "LAYER_CHANGE" || comment.starts_with("LAYER:") {
+                self.markers.push((None, position));
+            } else if let Some(Ok(z)) =
+                comment.strip_prefix("Z:").map(|z| z.trim().parse::<f32>())
+            {
+                self.z_comments.push((z, position));
+                self.set_marker_z(z);
+            }
+        }
+        match &line.command {
+            Some(Command::G0(m) | Command::G1(m)) => {
+                let from = self.position;
+                let to = move_target(&from, m, self.absolute, self.absolute_e);
+                self.position = to;
+                let z = to[2] as f32;
+                if to[2] != from[2] {
+                    self.z_move = (z, position);
+                }
+                // Only moves that push out filament confirm a layer so z
+                // hops, retractions and deretractions are not counted.
+                let last = self.z_moves.last().map_or(f32::MIN, |l| l.0);
+                let moved = to[0] != from[0] || to[1] != from[1];
+                if to[3] > from[3] && moved && z > last {
+                    self.z_moves.push(self.z_move);
+                    self.set_marker_z(z);
+                }
+            }
+            Some(Command::G90) => {
+                self.absolute = true;
+                self.absolute_e = true;
+            }
+            Some(Command::G91) => {
+                self.absolute = false;
+                self.absolute_e = false;
+            }
+            Some(Command::M82) => self.absolute_e = true,
+            Some(Command::M83) => self.absolute_e = false,
+            Some(Command::G92(axes)) => {
+                let values = [axes.x, axes.y, axes.z, axes.e];
+                for (axis, value) in values.into_iter().enumerate() {
+                    if let Some(v) = value {
+                        self.position[axis] = v as f64;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Give the last marker a height if it does not have one yet.
+    fn set_marker_z(
+        &mut self,
+        z: f32,
+    ) {
+        if let Some((marker_z @ None, _)) = self.markers.last_mut() {
+            *marker_z = Some(z);
+        }
+    }
+
+    /// Return the height and position of each layer found.
+    pub(crate) fn finish(self) -> Vec<(f32, usize)> {
+        if !self.markers.is_empty() {
+            let z = self.position[2] as f32;
+            self.markers
+                .into_iter()
+                .map(|(marker_z, p)| (marker_z.unwrap_or(z), p))
+                .collect()
+        } else if !self.z_comments.is_empty() {
+            self.z_comments
+        } else {
+            self.z_moves
+        }
+    }
+}
+
+impl LayerIndex {
+    /// Find the layers in the gcode blocks of a binary gcode file.
+    pub fn from_binary(binary: &[u8]) -> Result<Self, BinaryGcodeError> {
+        let (_, blocks) = deserialise_file(binary)?;
+        let mut detector = LayerDetector::default();
+        // Positions are packed as the block index and the offset in it.
+        let mut starts = Vec::new();
+        for (i, b) in blocks.iter().enumerate() {
+            if b.block.kind != BlockKind::GCode {
+                continue;
+            }
+            let gcode = b.block.gcode()?;
+            for line in parse_gcode(&gcode) {
+                let position = starts.len();
+                starts.push((i, line.span.start));
+                detector.line(&line, position);
+            }
+        }
+        let layers = detector
+            .finish()
+            .into_iter()
+            .map(|(z, position)| {
+                let (block_index, gcode_offset) = starts[position];
+                Layer {
+                    z,
+                    block_index,
+                    block_offset: blocks[block_index].range.start,
+                    gcode_offset,
+                }
+            })
+            .collect();
+        Ok(Self { layers })
+    }
+
+    /// Return the first layer at or above a height.
+    pub fn find(
+        &self,
+        z: f32,
+    ) -> Option<&Layer> {
+        // Allow for heights that were rounded when written.
+        self.layers.iter().find(|l| l.z >= z - 1e-4)
+    }
+
+    /// Serialise the index as space separated layers, each written as
+    /// `z,block_index,block_offset,gcode_offset`. This is the value
+    /// stored under [`LAYER_INDEX_KEY`] and can be used as a sidecar file.
+    pub fn to_metadata_value(&self) -> String {
+        let mut value = String::new();
+        for (i, l) in self.layers.iter().enumerate() {
+            if i > 0 {
+                value.push(' ');
+            }
+            // Writing to a string can not fail.
+            let _ = write!(
+                value,
+                "{},{},{},{}",
+                l.z, l.block_index, l.block_offset, l.gcode_offset
+            );
+        }
+        value
+    }
+
+    /// Parse an index written by [`LayerIndex::to_metadata_value`].
+    pub fn from_metadata_value(value: &str) -> Result<Self, BinaryGcodeError> {
+        let err = || BinaryGcodeError::InvalidMetadata(LAYER_INDEX_KEY);
+        let layers = value
+            .split_ascii_whitespace()
+            .map(|layer| {
+                let mut fields = layer.split(',');
+                let mut next = || fields.next().ok_or_else(err);
+                let layer = Layer {
+                    z: next()?.parse().map_err(|_| err())?,
+                    block_index: next()?.parse().map_err(|_| err())?,
+                    block_offset: next()?.parse().map_err(|_| err())?,
+                    gcode_offset: next()?.parse().map_err(|_| err())?,
+                };
+                match fields.next() {
+                    Some(_) => Err(err()),
+                    None => Ok(layer),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { layers })
+    }
+
+    /// Read an index stored in the print metadata of a file.
+    pub fn from_metadata(
+        binary: &[u8]
+    ) -> Result<Option<Self>, BinaryGcodeError> {
+        get_metadata(binary, BlockKind::PrintMetadata, LAYER_INDEX_KEY)?
+            .map(|v| Self::from_metadata_value(&v))
+            .transpose()
+    }
+}
+
+/// Build the layer index of a file and store it in the print metadata.
+/// The block offsets in the stored index account for the metadata block
+/// growing to hold it.
+pub fn embed_layer_index(binary: &[u8]) -> Result<Box<[u8]>, BinaryGcodeError> {
+    let kind = BlockKind::PrintMetadata;
+    // Make sure the key exists so the block indices are final.
+    let placeholder = set_metadata(binary, kind, LAYER_INDEX_KEY, "")?;
+    let mut index = LayerIndex::from_binary(&placeholder)?;
+    // The offsets change the length of the value they are stored in so
+    // repeat until they settle which is almost always the second pass.
+    for _ in 0..8 {
+        let value = index.to_metadata_value();
+        let out = set_metadata(&placeholder, kind, LAYER_INDEX_KEY, &value)?;
+        let (_, blocks) = deserialise_file(&out)?;
+        let mut settled = true;
+        for layer in index.layers.iter_mut() {
+            let offset = blocks[layer.block_index].range.start;
+            settled &= layer.block_offset == offset;
+            layer.block_offset = offset;
+        }
+        if settled {
+            return Ok(out);
+        }
+    }
+    Err(BinaryGcodeError::InvalidMetadata(LAYER_INDEX_KEY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{embed_layer_index, LayerIndex};
+    use crate::components::deserialiser::deserialise_file;
+    use crate::components::metadata::get_metadata;
+    use crate::BlockKind;
+
+    #[test]
+    fn layer_index_from_file() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let index = LayerIndex::from_binary(binary).unwrap();
+        // 0.2 first layer then 0.15 layers up to the max_layer_z.
+        assert_eq!(index.layers.len(), 120);
+        assert_eq!(index.layers[0].z, 0.2);
+        assert_eq!(index.layers.last().unwrap().z, 18.05);
+        assert!(index.find(9.0).unwrap().z >= 9.0);
+
+        let (_, blocks) = deserialise_file(binary).unwrap();
+        for layer in &index.layers {
+            let b = &blocks[layer.block_index];
+            assert_eq!(b.range.start, layer.block_offset);
+            let gcode = b.block.gcode().unwrap();
+            assert!(gcode[layer.gcode_offset..].starts_with(b";LAYER_CHANGE"));
+        }
+
+        let value = index.to_metadata_value();
+        assert_eq!(LayerIndex::from_metadata_value(&value).unwrap(), index);
+        assert!(LayerIndex::from_metadata_value("0.2,1,2").is_err());
+    }
+
+    #[test]
+    fn embedded_layer_index() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let embedded = embed_layer_index(binary).unwrap();
+        let stored = LayerIndex::from_metadata(&embedded).unwrap().unwrap();
+        assert_eq!(stored, LayerIndex::from_binary(&embedded).unwrap());
+        let filament = |b: &[u8]| {
+            get_metadata(b, BlockKind::PrintMetadata, "filament cost").unwrap()
+        };
+        assert_eq!(filament(binary), filament(&embedded));
+    }
+
+    #[test]
+    fn layers_from_z_moves() {
+        let gcode = b"G1 Z0.2\nG1 X1 E1\nG1 Z0.6\nG1 X2\nG1 Z0.4\nG1 X3 E2\n";
+        let mut detector = super::LayerDetector::default();
+        for (i, line) in crate::parse_gcode(gcode).enumerate() {
+            detector.line(&line, i);
+        }
+        // The hop to 0.6 does not extrude so it is not a layer.
+        assert_eq!(detector.finish(), [(0.2, 0), (0.4, 4)]);
+    }
+
+    #[test]
+    fn layers_from_absolute_e_z_moves() {
+        // The hop travels with E held at its absolute value and the
+        // retraction and deretraction around it do not move.
+        let gcode = b"M82\nG1 Z0.2\nG1 X1 E1\nG1 E0.2\nG1 Z0.6\nG1 X2 E0.2\nG1 Z0.4\nG1 E1\nG1 X3 E2\n";
+        let mut detector = super::LayerDetector::default();
+        for (i, line) in crate::parse_gcode(gcode).enumerate() {
+            detector.line(&line, i);
+        }
+        assert_eq!(detector.finish(), [(0.2, 1), (0.4, 6)]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Floating point functions missing from `core`. With the `std` feature
+//! the standard library versions are used.
+
+/// Return the square root of a number.
+pub(crate) fn sqrt(x: f64) -> f64 {
+    #[cfg(feature = "std")]
+    {
+        x.sqrt()
+    }
+    #[cfg(not(feature = "std"))]
+    sqrt_newton(x)
+}
+
+#[cfg_attr(feature = "std", allow(dead_code))]
+fn sqrt_newton(x: f64) -> f64 {
+    if x <= 0.0 || x.is_nan() || x.is_infinite() {
+        return if x == 0.0 || x.is_infinite() {
+            x
+        } else {
+            f64::NAN
+        };
+    }
+    // Halving the exponent is a close first guess which Newton's method
+    // then refines to full precision.
+    let mut y = f64::from_bits((x.to_bits() >> 1) + (1023u64 << 51));
+    for _ in 0..5 {
+        y = 0.5 * (y + x / y);
+    }
+    y
+}
+
+/// Return the angle of a point from the positive x axis in radians.
+pub(crate) fn atan2(
+    y: f64,
+    x: f64,
+) -> f64 {
+    #[cfg(feature = "std")]
+    {
+        y.atan2(x)
+    }
+    #[cfg(not(feature = "std"))]
+    atan2_series(y, x)
+}
+
+#[cfg_attr(feature = "std", allow(dead_code))]
+fn atan2_series(
+    y: f64,
+    x: f64,
+) -> f64 {
+    use core::f64::consts::{FRAC_PI_2, PI};
+
+    if x == 0.0 {
+        return match y {
+            y if y > 0.0 => FRAC_PI_2,
+            y if y < 0.0 => -FRAC_PI_2,
+            _ => 0.0,
+        };
+    }
+    let angle = atan(y / x);
+    match (x < 0.0, y < 0.0) {
+        (false, _) => angle,
+        (true, false) => angle + PI,
+        (true, true) => angle - PI,
+    }
+}
+
+/// Return the arc tangent by reducing the argument into `[0, 0.27]`
+/// where a short odd series is accurate to well below a micro radian.
+#[cfg_attr(feature = "std", allow(dead_code))]
+fn atan(x: f64) -> f64 {
+    use core::f64::consts::{FRAC_PI_2, FRAC_PI_6};
+
+    let (x, negative) = if x < 0.0 { (-x, true) } else { (x, false) };
+    let (x, inverted) = if x > 1.0 { (1.0 / x, true) } else { (x, false) };
+    // tan(30°) splits [0, 1] so the remainder is small.
+    let tan_30 = 0.577_350_269_189_625_8;
+    let (x, shifted) = if x > 0.267_949_192_431_122_7 {
+        ((x - tan_30) / (1.0 + tan_30 * x), true)
+    } else {
+        (x, false)
+    };
+    let x2 = x * x;
+    let mut angle = x
+        * (1.0
+            - x2 * (1.0 / 3.0
+                - x2 * (1.0 / 5.0
+                    - x2 * (1.0 / 7.0 - x2 * (1.0 / 9.0 - x2 / 11.0)))));
+    if shifted {
+        angle += FRAC_PI_6;
+    }
+    if inverted {
+        angle = FRAC_PI_2 - angle;
+    }
+    if negative {
+        -angle
+    } else {
+        angle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{atan2_series, sqrt_newton};
+
+    #[test]
+    fn portable_matches_std() {
+        for i in 0..2000 {
+            let x = i as f64 * 0.173 - 150.0;
+            let y = (i as f64 * 0.61).sin() * 90.0;
+            let root = x.abs().sqrt();
+            assert!((sqrt_newton(x.abs()) - root).abs() <= root * 1e-15);
+            assert!((atan2_series(y, x) - y.atan2(x)).abs() < 1e-8);
+        }
+        assert_eq!(atan2_series(0.0, -1.0), core::f64::consts::PI);
+    }
+}
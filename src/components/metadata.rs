@@ -0,0 +1,199 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::str;
+
+use crate::components::common::{
+    BinaryGcodeError, BlockKind, CompressionAlgorithm, Encoding,
+};
+use crate::components::deserialiser::deserialise_file;
+use crate::components::serialiser::serialise_block;
+
+/// Split a line of a metadata block into its key and value. Both the
+/// `key=value` lines written by PrusaSlicer and the `; key = value`
+/// comments kept by `ascii_to_binary` are accepted.
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start().trim_start_matches(';');
+    let (key, value) = line.split_once('=')?;
+    Some((key.trim(), value.trim()))
+}
+
+/// Return the key value pairs of the data of a metadata block.
+pub fn parse_metadata(
+    data: &[u8]
+) -> Result<Vec<(&str, &str)>, BinaryGcodeError> {
+    let data = str::from_utf8(data)
+        .map_err(|_| BinaryGcodeError::InvalidMetadata("utf8"))?;
+    Ok(data.lines().filter_map(split_line).collect())
+}
+
+/// Return the value of a key in the first metadata block of a kind.
+pub fn get_metadata(
+    binary: &[u8],
+    kind: BlockKind,
+    key: &str,
+) -> Result<Option<String>, BinaryGcodeError> {
+    if matches!(kind, BlockKind::GCode | BlockKind::Thumbnail) {
+        return Err(BinaryGcodeError::NotMetadata(kind));
+    }
+    let (_, blocks) = deserialise_file(binary)?;
+    let Some(b) = blocks.iter().find(|b| b.block.kind == kind) else {
+        return Ok(None);
+    };
+    let data = b.block.decompress()?;
+    let value = parse_metadata(&data)?
+        .into_iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| String::from(v));
+    Ok(value)
+}
+
+/// Set the value of a key in the first metadata block of a kind, adding
+/// the key or the block if required. The block keeps its compression and
+/// line style and every other block is copied through untouched.
+pub fn set_metadata(
+    binary: &[u8],
+    kind: BlockKind,
+    key: &str,
+    value: &str,
+) -> Result<Box<[u8]>, BinaryGcodeError> {
+    if matches!(kind, BlockKind::GCode | BlockKind::Thumbnail) {
+        return Err(BinaryGcodeError::NotMetadata(kind));
+    }
+    if key.is_empty() || key.contains(['=', '\n', '\r', ';']) {
+        return Err(BinaryGcodeError::InvalidMetadata("key"));
+    }
+    if value.contains(['\n', '\r']) {
+        return Err(BinaryGcodeError::InvalidMetadata("value"));
+    }
+    let (header, blocks) = deserialise_file(binary)?;
+
+    let existing = blocks.iter().position(|b| b.block.kind == kind);
+    let (compression, data) = match existing {
+        Some(i) => {
+            let block = &blocks[i].block;
+            (block.compression, block.decompress()?)
+        }
+        None => (CompressionAlgorithm::None, Box::default()),
+    };
+    let data = str::from_utf8(&data)
+        .map_err(|_| BinaryGcodeError::InvalidMetadata("utf8"))?;
+
+    // Follow the style of the existing lines.
+    let commented = data.trim_start().starts_with(';');
+    let mut line = String::new();
+    if commented {
+        line.push_str("; ");
+        line.push_str(key);
+        line.push_str(" = ");
+    } else {
+        line.push_str(key);
+        line.push('=');
+    }
+    line.push_str(value);
+
+    let mut out = String::with_capacity(data.len() + line.len() + 1);
+    let mut replaced = false;
+    for l in data.split_inclusive('\n') {
+        if !replaced && split_line(l).is_some_and(|(k, _)| k == key) {
+            out.push_str(&line);
+            out.push('\n');
+            replaced = true;
+        } else {
+            out.push_str(l);
+        }
+    }
+    if !replaced {
+        // Keep any blank lines that end the block after the new key.
+        let body_len = out.trim_end_matches('\n').len();
+        let tail = out.split_off(body_len);
+        if body_len > 0 {
+            out.push('\n');
+        }
+        out.push_str(&line);
+        out.push_str(if tail.is_empty() { "\n" } else { &tail });
+    }
+
+    let block = serialise_block(
+        kind,
+        compression,
+        Encoding::Ini,
+        header.checksum,
+        &[],
+        out.as_bytes(),
+    )?;
+
+    // A new block goes where the specification orders it.
+    let at = existing.unwrap_or_else(|| {
+        blocks
+            .iter()
+            .position(|b| b.block.kind.file_order() > kind.file_order())
+            .unwrap_or(blocks.len())
+    });
+    let header_end = blocks.first().map_or(binary.len(), |b| b.range.start);
+    let mut result = Vec::with_capacity(binary.len() + line.len());
+    result.extend_from_slice(&binary[..header_end]);
+    for (i, b) in blocks.iter().enumerate() {
+        if i == at {
+            result.extend_from_slice(&block);
+        }
+        if Some(i) != existing {
+            result.extend_from_slice(&binary[b.range.clone()]);
+        }
+    }
+    if at == blocks.len() {
+        result.extend_from_slice(&block);
+    }
+    Ok(result.into_boxed_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_metadata, set_metadata};
+    use crate::components::common::BlockKind;
+    use crate::components::thumbnail::thumbnails;
+    use crate::{ascii_to_binary, binary_to_ascii};
+    use alloc::borrow::ToOwned;
+
+    #[test]
+    fn set_and_get_metadata() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let kind = BlockKind::PrintMetadata;
+        let used = get_metadata(binary, kind, "filament used [mm]").unwrap();
+        assert_eq!(used.as_deref(), Some("986.61"));
+
+        let edited =
+            set_metadata(binary, kind, "filament used [mm]", "1000").unwrap();
+        let edited = set_metadata(&edited, kind, "job", "42").unwrap();
+        let get = |key| get_metadata(&edited, kind, key).unwrap();
+        assert_eq!(get("filament used [mm]").as_deref(), Some("1000"));
+        assert_eq!(get("job").as_deref(), Some("42"));
+        assert_eq!(get("filament cost").as_deref(), Some("0.08"));
+        assert_eq!(thumbnails(&edited).unwrap().len(), 2);
+
+        // The gcode is untouched.
+        let gcode = |b: &[u8]| {
+            let ascii = binary_to_ascii(b, false).unwrap();
+            let start = ascii.find("M73").unwrap();
+            ascii[start..].to_owned()
+        };
+        assert_eq!(gcode(binary), gcode(&edited));
+        assert!(set_metadata(binary, BlockKind::GCode, "a", "b").is_err());
+        assert!(set_metadata(binary, kind, "a=b", "c").is_err());
+    }
+
+    #[test]
+    fn set_metadata_commented() {
+        let gcode = include_str!("../../test_files/mini_cube_ps2.8.1.gcode");
+        let binary = ascii_to_binary(gcode).unwrap();
+        let kind = BlockKind::PrinterMetadata;
+        let edited =
+            set_metadata(&binary, kind, "printer_model", "MK4").unwrap();
+        assert_eq!(
+            get_metadata(&edited, kind, "printer_model")
+                .unwrap()
+                .as_deref(),
+            Some("MK4")
+        );
+        let ascii = binary_to_ascii(&edited, false).unwrap();
+        assert!(ascii.contains("; printer_model = MK4\n"));
+    }
+}
@@ -1,7 +1,21 @@
 pub(crate) mod common;
 pub(crate) mod convert;
+pub(crate) mod crc;
 pub(crate) mod deserialiser;
+pub(crate) mod gcode;
+pub(crate) mod layers;
+pub(crate) mod math;
+pub(crate) mod metadata;
+pub(crate) mod preview;
+pub(crate) mod qoi;
+pub(crate) mod resume;
 pub(crate) mod serialiser;
+pub(crate) mod stats;
+pub(crate) mod thumbnail;
+pub(crate) mod time;
+pub(crate) mod transcode;
+pub(crate) mod verify;
 
 #[cfg(test)]
+#[allow(clippy::single_match)]
 mod tests;
@@ -0,0 +1,363 @@
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::components::common::{BinaryGcodeError, BlockKind};
+use crate::components::deserialiser::deserialise_file;
+use crate::components::gcode::{parse_gcode, Command, Move};
+use crate::components::thumbnail::{
+    edit_thumbnails, RgbaImage, Thumbnail, ThumbnailEdit, ThumbnailFormat,
+};
+
+/// The colour of the extruded paths, shaded darker towards the bed.
+const FILAMENT: [f32; 3] = [237.0, 107.0, 33.0];
+/// The extrusion width drawn in millimetres.
+const LINE_WIDTH: f32 = 0.45;
+/// The margin around the model as a fraction of the image size.
+const MARGIN: f32 = 0.05;
+const COS_30: f32 = 0.866_025_4;
+const SIN_30: f32 = 0.5;
+
+/// The projection used to render a toolpath preview.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum PreviewView {
+    /// Looking down onto the bed.
+    TopDown,
+    /// Looking at the front left corner from above.
+    #[default]
+    Isometric,
+}
+
+impl PreviewView {
+    /// Project a point onto the image plane with y pointing down.
+    fn project(
+        &self,
+        [x, y, z]: [f32; 3],
+    ) -> [f32; 2] {
+        match self {
+            PreviewView::TopDown => [x, -y],
+            PreviewView::Isometric => {
+                [(x - y) * COS_30, -((x + y) * SIN_30 + z)]
+            }
+        }
+    }
+}
+
+/// An extruding move between two points.
+struct Segment {
+    from: [f32; 3],
+    to: [f32; 3],
+    /// Whether the slicer tagged the move as part of the printed object
+    /// rather than custom gcode such as a purge line.
+    object: bool,
+}
+
+/// Follows the positioning modes of the gcode to find every move that
+/// extrudes filament.
+struct Toolpath {
+    position: [f32; 4],
+    absolute: bool,
+    absolute_e: bool,
+    /// Whether the moves since the last `;TYPE:` comment are part of the
+    /// object, or `None` if the slicer does not tag its moves.
+    object: Option<bool>,
+    segments: Vec<Segment>,
+}
+
+impl Toolpath {
+    fn new() -> Self {
+        Self {
+            position: [0.0; 4],
+            absolute: true,
+            absolute_e: true,
+            object: None,
+            segments: Vec::new(),
+        }
+    }
+
+    fn parse(
+        &mut self,
+        gcode: &[u8],
+    ) {
+        for line in parse_gcode(gcode) {
+            if let Some(kind) =
+                line.comment_str().and_then(|c| c.strip_prefix("TYPE:"))
+            {
+                self.object = Some(kind != "Custom");
+            }
+            match line.command {
+                Some(Command::G0(m) | Command::G1(m)) => self.travel(m),
+                // Arcs are drawn as a straight line which is close enough
+                // at thumbnail sizes.
+                Some(Command::G2(a) | Command::G3(a)) => self.travel(Move {
+                    x: a.x,
+                    y: a.y,
+                    z: a.z,
+                    e: a.e,
+                    f: a.f,
+                }),
+                Some(Command::G90) => {
+                    self.absolute = true;
+                    self.absolute_e = true;
+                }
+                Some(Command::G91) => {
+                    self.absolute = false;
+                    self.absolute_e = false;
+                }
+                Some(Command::M82) => self.absolute_e = true,
+                Some(Command::M83) => self.absolute_e = false,
+                Some(Command::G92(axes)) => {
+                    let values = [axes.x, axes.y, axes.z, axes.e];
+                    for (axis, value) in values.into_iter().enumerate() {
+                        if let Some(v) = value {
+                            self.position[axis] = v;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Move to the end of a move following the positioning modes.
+    fn travel(
+        &mut self,
+        m: Move,
+    ) {
+        let from = self.position;
+        let mut to = from;
+        for (axis, value) in [m.x, m.y, m.z, m.e].into_iter().enumerate() {
+            let Some(value) = value else {
+                continue;
+            };
+            let absolute = match axis {
+                3 => self.absolute_e,
+                _ => self.absolute,
+            };
+            to[axis] = if absolute { value } else { from[axis] + value };
+        }
+        let moved = from[..3] != to[..3];
+        if moved && to[3] > from[3] {
+            self.segments.push(Segment {
+                from: [from[0], from[1], from[2]],
+                to: [to[0], to[1], to[2]],
+                object: self.object == Some(true),
+            });
+        }
+        self.position = to;
+    }
+}
+
+/// Render the extruding moves of some ascii gcode into an image with a
+/// transparent background. The model is scaled to fit the image.
+pub fn render_preview(
+    gcode: &[u8],
+    view: PreviewView,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage, BinaryGcodeError> {
+    let mut toolpath = Toolpath::new();
+    toolpath.parse(gcode);
+    render_toolpath(toolpath, view, width, height)
+}
+
+fn render_toolpath(
+    toolpath: Toolpath,
+    view: PreviewView,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage, BinaryGcodeError> {
+    if width == 0 || height == 0 {
+        return Err(BinaryGcodeError::InvalidThumbnail("dimensions"));
+    }
+    // Leave out purge lines and the like when the slicer tags its moves.
+    let mut segments = toolpath.segments;
+    if segments.iter().any(|s| s.object) {
+        segments.retain(|s| s.object);
+    }
+    if segments.is_empty() {
+        return Err(BinaryGcodeError::NoExtrusionMoves);
+    }
+
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    let mut lo = [f32::MAX; 2];
+    let mut hi = [f32::MIN; 2];
+    for point in segments.iter().flat_map(|s| [s.from, s.to]) {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+        let p = view.project(point);
+        for axis in 0..2 {
+            lo[axis] = lo[axis].min(p[axis]);
+            hi[axis] = hi[axis].max(p[axis]);
+        }
+    }
+
+    // Fit the projected model inside the margins keeping its aspect.
+    let (w, h) = (width as f32, height as f32);
+    let span = [
+        (hi[0] - lo[0]).max(LINE_WIDTH),
+        (hi[1] - lo[1]).max(LINE_WIDTH),
+    ];
+    let scale = (w * (1.0 - 2.0 * MARGIN) / span[0])
+        .min(h * (1.0 - 2.0 * MARGIN) / span[1]);
+    let offset = [
+        (w - span[0] * scale) / 2.0 - lo[0] * scale,
+        (h - span[1] * scale) / 2.0 - lo[1] * scale,
+    ];
+    let to_pixels = |point: [f32; 3]| {
+        let [x, y] = view.project(point);
+        [x * scale + offset[0], y * scale + offset[1]]
+    };
+    let radius = (LINE_WIDTH * scale / 2.0).max(0.7);
+    let z_span = (max[2] - min[2]).max(f32::EPSILON);
+
+    // Later moves are printed on top so they are drawn over earlier ones.
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    for segment in &segments {
+        let a = to_pixels(segment.from);
+        let b = to_pixels(segment.to);
+        let mut shade = 0.55 + 0.45 * (segment.to[2] - min[2]) / z_span;
+        if view == PreviewView::Isometric {
+            // Light the walls facing along x and y differently.
+            let dx = (segment.to[0] - segment.from[0]).abs();
+            let dy = (segment.to[1] - segment.from[1]).abs();
+            shade *= 0.75 + 0.25 * dx / (dx + dy).max(f32::EPSILON);
+        }
+        let colour = FILAMENT.map(|c| (c * shade) as u8);
+        draw_line(&mut pixels, width, height, a, b, radius, colour);
+    }
+
+    Ok(RgbaImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Fill every pixel whose centre is within the radius of a line.
+fn draw_line(
+    pixels: &mut [u8],
+    width: u32,
+    height: u32,
+    a: [f32; 2],
+    b: [f32; 2],
+    radius: f32,
+    colour: [u8; 3],
+) {
+    let clamp = |v: f32, max: u32| (v.max(0.0) as u32).min(max);
+    let x0 = clamp(a[0].min(b[0]) - radius, width);
+    let x1 = clamp(a[0].max(b[0]) + radius + 1.0, width);
+    let y0 = clamp(a[1].min(b[1]) - radius, height);
+    let y1 = clamp(a[1].max(b[1]) + radius + 1.0, height);
+    let d = [b[0] - a[0], b[1] - a[1]];
+    let len2 = d[0] * d[0] + d[1] * d[1];
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let p = [x as f32 + 0.5 - a[0], y as f32 + 0.5 - a[1]];
+            let t = match len2 > 0.0 {
+                true => ((p[0] * d[0] + p[1] * d[1]) / len2).clamp(0.0, 1.0),
+                false => 0.0,
+            };
+            let dx = p[0] - t * d[0];
+            let dy = p[1] - t * d[1];
+            if dx * dx + dy * dy <= radius * radius {
+                let i = (y as usize * width as usize + x as usize) * 4;
+                pixels[i..i + 4]
+                    .copy_from_slice(&[colour[0], colour[1], colour[2], 255]);
+            }
+        }
+    }
+}
+
+/// Render a preview of the gcode blocks in a binary gcode file and add
+/// it as a thumbnail, replacing any existing thumbnail of the same size.
+pub fn add_preview_thumbnail(
+    binary: &[u8],
+    view: PreviewView,
+    format: ThumbnailFormat,
+    width: u16,
+    height: u16,
+) -> Result<Box<[u8]>, BinaryGcodeError> {
+    let (_, mut blocks) = deserialise_file(binary)?;
+    let mut toolpath = Toolpath::new();
+    let mut gcode = Vec::new();
+    for b in blocks
+        .iter_mut()
+        .filter(|b| b.block.kind == BlockKind::GCode)
+    {
+        gcode.clear();
+        b.block.to_ascii(&mut gcode, false)?;
+        toolpath.parse(&gcode);
+    }
+    let image = render_toolpath(toolpath, view, width as u32, height as u32)?;
+    let thumbnail = Thumbnail {
+        format,
+        width,
+        height,
+        data: image.encode(format)?,
+    };
+    edit_thumbnails(binary, &[ThumbnailEdit::Replace(thumbnail)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_preview_thumbnail, render_preview, PreviewView};
+    use crate::components::thumbnail::{
+        edit_thumbnails, thumbnails, ThumbnailEdit, ThumbnailFormat,
+    };
+    use crate::BinaryGcodeError;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn render_gcode_preview() {
+        let gcode = include_bytes!("../../test_files/mini_cube_b.gcode");
+        for view in [PreviewView::TopDown, PreviewView::Isometric] {
+            let image = render_preview(gcode, view, 64, 48).unwrap();
+            let drawn = image.pixels.chunks_exact(4).filter(|p| p[3] == 255);
+            assert!(drawn.count() > 64 * 48 / 10);
+            // The margins are left transparent.
+            assert_eq!(image.pixels[..4], [0, 0, 0, 0]);
+        }
+        assert!(matches!(
+            render_preview(b"G1 X10 Y10\n", PreviewView::TopDown, 16, 16),
+            Err(BinaryGcodeError::NoExtrusionMoves)
+        ));
+    }
+
+    #[test]
+    fn relative_extrusion() {
+        let gcode = b"M83\nG1 X0 Y0 Z0.2\nG1 X10 E1\nG1 Y10 E-1\n";
+        let image =
+            render_preview(gcode, PreviewView::TopDown, 20, 20).unwrap();
+        // Only the first move along x is drawn across the middle.
+        let drawn: Vec<usize> = image
+            .pixels
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, p)| p[3] == 255)
+            .map(|(i, _)| i / 20)
+            .collect();
+        assert!(drawn.len() >= 18);
+        assert!(drawn.iter().all(|y| (8..12).contains(y)));
+    }
+
+    #[test]
+    fn preview_thumbnail_in_file() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let binary =
+            edit_thumbnails(binary, &[ThumbnailEdit::RemoveAll]).unwrap();
+        let binary = add_preview_thumbnail(
+            &binary,
+            PreviewView::Isometric,
+            ThumbnailFormat::Qoi,
+            32,
+            24,
+        )
+        .unwrap();
+        let thumbs = thumbnails(&binary).unwrap();
+        assert_eq!(thumbs.len(), 1);
+        assert_eq!((thumbs[0].width, thumbs[0].height), (32, 24));
+        assert_eq!(thumbs[0].format, ThumbnailFormat::Qoi);
+    }
+}
@@ -0,0 +1,259 @@
+//! A `no_std` implementation of the
+//! [Quite OK Image Format](https://qoiformat.org/qoi-specification.pdf)
+//! that Prusa firmware prefers for thumbnails as it is fast to decode.
+
+use alloc::vec::Vec;
+
+use crate::components::common::BinaryGcodeError;
+use crate::components::thumbnail::RgbaImage;
+
+const QOI_MAGIC: [u8; 4] = *b"qoif";
+const QOI_HEADER_SIZE: usize = 14;
+const QOI_END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+/// Guards against allocating huge buffers for corrupted headers.
+const QOI_PIXELS_MAX: u64 = 400_000_000;
+
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RUN: u8 = 0xC0;
+const QOI_OP_RGB: u8 = 0xFE;
+const QOI_OP_RGBA: u8 = 0xFF;
+const QOI_MASK_2: u8 = 0xC0;
+
+/// The header of a QOI image.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct QoiHeader {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub colorspace: u8,
+}
+
+const fn hash(px: [u8; 4]) -> usize {
+    let [r, g, b, a] = px;
+    (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+/// Read and check the header of a QOI image along with its end marker.
+pub(crate) fn header(data: &[u8]) -> Result<QoiHeader, BinaryGcodeError> {
+    if data.len() < QOI_HEADER_SIZE + QOI_END_MARKER.len() {
+        return Err(BinaryGcodeError::InvalidThumbnail("qoi_length"));
+    }
+    if data[0..4] != QOI_MAGIC {
+        return Err(BinaryGcodeError::InvalidThumbnail("qoi_magic"));
+    }
+    if !data.ends_with(&QOI_END_MARKER) {
+        return Err(BinaryGcodeError::InvalidThumbnail("qoi_end_marker"));
+    }
+    let header = QoiHeader {
+        width: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        height: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+        channels: data[12],
+        colorspace: data[13],
+    };
+    if header.width == 0
+        || header.height == 0
+        || header.width as u64 * header.height as u64 > QOI_PIXELS_MAX
+    {
+        return Err(BinaryGcodeError::InvalidThumbnail("qoi_dimensions"));
+    }
+    if !(3..=4).contains(&header.channels) || header.colorspace > 1 {
+        return Err(BinaryGcodeError::InvalidThumbnail("qoi_channels"));
+    }
+    Ok(header)
+}
+
+/// Decode a QOI image into RGBA pixels.
+pub(crate) fn decode(data: &[u8]) -> Result<RgbaImage, BinaryGcodeError> {
+    let header = header(data)?;
+    let px_len = header.width as usize * header.height as usize * 4;
+    let mut pixels = Vec::with_capacity(px_len);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0u8, 0, 0, 255];
+    let chunks = &data[QOI_HEADER_SIZE..data.len() - QOI_END_MARKER.len()];
+    let mut p = 0;
+    let mut run = 0;
+
+    // Reads the next byte of the chunks.
+    let next = |p: &mut usize| -> Result<u8, BinaryGcodeError> {
+        let b = chunks
+            .get(*p)
+            .ok_or(BinaryGcodeError::InvalidThumbnail("qoi_truncated"))?;
+        *p += 1;
+        Ok(*b)
+    };
+
+    while pixels.len() < px_len {
+        if run > 0 {
+            run -= 1;
+        } else {
+            let b1 = next(&mut p)?;
+            if b1 == QOI_OP_RGB {
+                px[0] = next(&mut p)?;
+                px[1] = next(&mut p)?;
+                px[2] = next(&mut p)?;
+            } else if b1 == QOI_OP_RGBA {
+                px[0] = next(&mut p)?;
+                px[1] = next(&mut p)?;
+                px[2] = next(&mut p)?;
+                px[3] = next(&mut p)?;
+            } else {
+                match b1 & QOI_MASK_2 {
+                    QOI_OP_INDEX => px = index[b1 as usize],
+                    QOI_OP_DIFF => {
+                        px[0] = px[0]
+                            .wrapping_add(((b1 >> 4) & 0x03).wrapping_sub(2));
+                        px[1] = px[1]
+                            .wrapping_add(((b1 >> 2) & 0x03).wrapping_sub(2));
+                        px[2] = px[2].wrapping_add((b1 & 0x03).wrapping_sub(2));
+                    }
+                    QOI_OP_LUMA => {
+                        let b2 = next(&mut p)?;
+                        let vg = (b1 & 0x3F).wrapping_sub(32);
+                        px[0] = px[0]
+                            .wrapping_add(vg.wrapping_sub(8))
+                            .wrapping_add((b2 >> 4) & 0x0F);
+                        px[1] = px[1].wrapping_add(vg);
+                        px[2] = px[2]
+                            .wrapping_add(vg.wrapping_sub(8))
+                            .wrapping_add(b2 & 0x0F);
+                    }
+                    // QOI_OP_RUN
+                    _ => run = b1 & 0x3F,
+                }
+            }
+            index[hash(px)] = px;
+        }
+        pixels.extend(px);
+    }
+
+    Ok(RgbaImage {
+        width: header.width,
+        height: header.height,
+        pixels,
+    })
+}
+
+/// Encode RGBA pixels into a QOI image. Four channels are always declared
+/// in the header, matching the thumbnails written by PrusaSlicer.
+pub(crate) fn encode(image: &RgbaImage) -> Result<Vec<u8>, BinaryGcodeError> {
+    let px_len = image.width as usize * image.height as usize * 4;
+    if image.width == 0
+        || image.height == 0
+        || image.pixels.len() != px_len
+        || image.width as u64 * image.height as u64 > QOI_PIXELS_MAX
+    {
+        return Err(BinaryGcodeError::InvalidThumbnail("qoi_dimensions"));
+    }
+
+    let mut out = Vec::with_capacity(QOI_HEADER_SIZE + px_len / 2);
+    out.extend(QOI_MAGIC);
+    out.extend(image.width.to_be_bytes());
+    out.extend(image.height.to_be_bytes());
+    out.push(4);
+    // sRGB with linear alpha.
+    out.push(0);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0u8, 0, 0, 255];
+    let mut run: u8 = 0;
+    let last = px_len / 4 - 1;
+
+    for (i, px) in image.pixels.chunks_exact(4).enumerate() {
+        let px = [px[0], px[1], px[2], px[3]];
+        if px == prev {
+            run += 1;
+            if run == 62 || i == last {
+                out.push(QOI_OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(QOI_OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let idx = hash(px);
+        if index[idx] == px {
+            out.push(QOI_OP_INDEX | idx as u8);
+        } else {
+            index[idx] = px;
+            if px[3] == prev[3] {
+                let vr = px[0].wrapping_sub(prev[0]) as i8;
+                let vg = px[1].wrapping_sub(prev[1]) as i8;
+                let vb = px[2].wrapping_sub(prev[2]) as i8;
+                let vg_r = vr.wrapping_sub(vg);
+                let vg_b = vb.wrapping_sub(vg);
+                if (-2..=1).contains(&vr)
+                    && (-2..=1).contains(&vg)
+                    && (-2..=1).contains(&vb)
+                {
+                    out.push(
+                        QOI_OP_DIFF
+                            | ((vr + 2) as u8) << 4
+                            | ((vg + 2) as u8) << 2
+                            | (vb + 2) as u8,
+                    );
+                } else if (-8..=7).contains(&vg_r)
+                    && (-32..=31).contains(&vg)
+                    && (-8..=7).contains(&vg_b)
+                {
+                    out.push(QOI_OP_LUMA | (vg + 32) as u8);
+                    out.push(((vg_r + 8) as u8) << 4 | (vg_b + 8) as u8);
+                } else {
+                    out.extend([QOI_OP_RGB, px[0], px[1], px[2]]);
+                }
+            } else {
+                out.extend([QOI_OP_RGBA, px[0], px[1], px[2], px[3]]);
+            }
+        }
+        prev = px;
+    }
+
+    out.extend(QOI_END_MARKER);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, header};
+    use crate::components::thumbnail::{thumbnails, RgbaImage};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn qoi_round_trip() {
+        // A gradient with transparency, runs and repeated colours.
+        let mut pixels = Vec::new();
+        for y in 0..37u32 {
+            for x in 0..53u32 {
+                let a = if x > 40 { 128 } else { 255 };
+                let v = if y > 30 { 0 } else { (x * 5 + y * 3) as u8 };
+                pixels.extend([v, v.wrapping_mul(3), 255 - v, a]);
+            }
+        }
+        let image = RgbaImage {
+            width: 53,
+            height: 37,
+            pixels,
+        };
+        let encoded = encode(&image).unwrap();
+        assert_eq!(header(&encoded).unwrap().channels, 4);
+        assert_eq!(decode(&encoded).unwrap(), image);
+    }
+
+    #[test]
+    fn qoi_test_file() {
+        let binary =
+            include_bytes!("../../test_files/mini_cube_ps2.8.1.bgcode");
+        for thumb in thumbnails(binary).unwrap() {
+            let image = decode(&thumb.data).unwrap();
+            assert_eq!(image.width, thumb.width as u32);
+            assert_eq!(image.height, thumb.height as u32);
+            // Re-encoding produces the same image as PrusaSlicer.
+            assert_eq!(encode(&image).unwrap().as_slice(), thumb.data.as_ref());
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt::Write;
+
+use crate::components::common::{BinaryGcodeError, BlockKind};
+use crate::components::convert::gcode_chunks;
+use crate::components::deserialiser::deserialise_file;
+use crate::components::gcode::{parse_gcode, Command, GcodeLine, Move};
+use crate::components::layers::{embed_layer_index, LayerIndex};
+use crate::components::serialiser::{pack_gcode, serialise_block};
+use crate::components::stats::move_target;
+
+/// How far above the print the nozzle travels to the resume position.
+const RESUME_CLEARANCE: f64 = 1.0;
+
+/// The printer state that has to be restored to carry on from a point in
+/// the gcode.
+struct PrinterState {
+    position: [f64; 4],
+    absolute: bool,
+    absolute_e: bool,
+    /// The last feedrate in mm/min.
+    feedrate: Option<f32>,
+    tool: Option<u16>,
+    /// The hotend temperatures indexed by tool.
+    hotends: Vec<Option<f32>>,
+    bed: Option<f32>,
+    /// The fan speeds indexed by fan.
+    fans: Vec<Option<f32>>,
+    speed_factor: Option<f32>,
+    flow_factor: Option<f32>,
+}
+
+impl PrinterState {
+    fn new() -> Self {
+        Self {
+            position: [0.0; 4],
+            absolute: true,
+            absolute_e: true,
+            feedrate: None,
+            tool: None,
+            hotends: Vec::new(),
+            bed: None,
+            fans: Vec::new(),
+            speed_factor: None,
+            flow_factor: None,
+        }
+    }
+
+    fn line(
+        &mut self,
+        line: &GcodeLine,
+    ) {
+        // Store a value in a list that grows to fit it.
+        fn set(
+            values: &mut Vec<Option<f32>>,
+            index: Option<f32>,
+            value: f32,
+        ) {
+            let index = index.map_or(0, |i| i as usize);
+            if values.len() <= index {
+                values.resize(index + 1, None);
+            }
+            values[index] = Some(value);
+        }
+
+        let tool = self.tool.map(f32::from);
+        match &line.command {
+            Some(Command::G0(m) | Command::G1(m)) => self.travel(m),
+            Some(Command::G2(a) | Command::G3(a)) => self.travel(&Move {
+                x: a.x,
+                y: a.y,
+                z: a.z,
+                e: a.e,
+                f: a.f,
+            }),
+            Some(Command::G90) => {
+                self.absolute = true;
+                self.absolute_e = true;
+            }
+            Some(Command::G91) => {
+                self.absolute = false;
+                self.absolute_e = false;
+            }
+            Some(Command::M82) => self.absolute_e = true,
+            Some(Command::M83) => self.absolute_e = false,
+            Some(Command::G92(axes)) => {
+                let values = [axes.x, axes.y, axes.z, axes.e];
+                for (axis, value) in values.into_iter().enumerate() {
+                    if let Some(v) = value {
+                        self.position[axis] = v as f64;
+                    }
+                }
+            }
+            Some(Command::M104 { s: Some(s), t }) => {
+                set(&mut self.hotends, t.or(tool), *s)
+            }
+            Some(Command::M109 { s, r, t }) => {
+                if let Some(s) = s.or(*r) {
+                    set(&mut self.hotends, t.or(tool), s);
+                }
+            }
+            Some(Command::M140 { s: Some(s) }) => self.bed = Some(*s),
+            Some(Command::M190 { s, r }) => {
+                if let Some(s) = s.or(*r) {
+                    self.bed = Some(s);
+                }
+            }
+            Some(Command::M106 { p, s }) => {
+                set(&mut self.fans, *p, s.unwrap_or(255.0))
+            }
+            Some(Command::M107 { p }) => set(&mut self.fans, *p, 0.0),
+            Some(Command::M220 { s: Some(s) }) => self.speed_factor = Some(*s),
+            Some(Command::M221 { s: Some(s), .. }) => {
+                self.flow_factor = Some(*s)
+            }
+            Some(Command::T(t)) => self.tool = Some(*t),
+            _ => {}
+        }
+    }
+
+    fn travel(
+        &mut self,
+        m: &Move,
+    ) {
+        if m.f.is_some() {
+            self.feedrate = m.f;
+        }
+        self.position =
+            move_target(&self.position, m, self.absolute, self.absolute_e);
+    }
+
+    /// Return the gcode that takes a printer from the end of the start
+    /// gcode back to this state.
+    fn restore(
+        &self,
+        layer_z: f32,
+    ) -> String {
+        let mut out = String::new();
+        // Writing to a string can not fail.
+        let _ = self.write_restore(layer_z, &mut out);
+        out
+    }
+
+    fn write_restore(
+        &self,
+        layer_z: f32,
+        out: &mut String,
+    ) -> core::fmt::Result {
+        let multiple_tools = self.hotends.len() > 1;
+        writeln!(out, ";RESUME from Z{}", layer_z)?;
+        if let Some(t) = self.tool {
+            writeln!(out, "T{}", t)?;
+        }
+        // Start heating everything before waiting on any of it.
+        if let Some(bed) = self.bed {
+            writeln!(out, "M140 S{}", bed)?;
+        }
+        let hotends = self
+            .hotends
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| t.map(|t| (i, t)));
+        for (i, temperature) in hotends.clone() {
+            match multiple_tools {
+                true => writeln!(out, "M104 T{} S{}", i, temperature)?,
+                false => writeln!(out, "M104 S{}", temperature)?,
+            }
+        }
+        if let Some(bed) = self.bed {
+            writeln!(out, "M190 S{}", bed)?;
+        }
+        for (i, temperature) in hotends {
+            match multiple_tools {
+                true => writeln!(out, "M109 T{} S{}", i, temperature)?,
+                false => writeln!(out, "M109 S{}", temperature)?,
+            }
+        }
+
+        // Come down onto the print from above it.
+        let [x, y, z, e] = self.position;
+        writeln!(out, "G90")?;
+        writeln!(out, "G1 Z{:.3} F720", z + RESUME_CLEARANCE)?;
+        writeln!(out, "G1 X{:.3} Y{:.3} F3000", x, y)?;
+        writeln!(out, "G1 Z{:.3} F720", z)?;
+        if !self.absolute {
+            writeln!(out, "G91")?;
+        }
+        match self.absolute_e {
+            true => writeln!(out, "M82\nG92 E{:.5}", e)?,
+            false => writeln!(out, "M83")?,
+        }
+        if let Some(f) = self.feedrate {
+            writeln!(out, "G1 F{}", f)?;
+        }
+
+        for (i, speed) in self.fans.iter().enumerate() {
+            match (speed, i) {
+                (None, _) => {}
+                (Some(s), 0) if *s > 0.0 => writeln!(out, "M106 S{}", s)?,
+                (Some(s), _) if *s > 0.0 => {
+                    writeln!(out, "M106 P{} S{}", i, s)?
+                }
+                (Some(_), 0) => writeln!(out, "M107")?,
+                (Some(_), _) => writeln!(out, "M107 P{}", i)?,
+            }
+        }
+        if let Some(s) = self.speed_factor {
+            writeln!(out, "M220 S{}", s)?;
+        }
+        if let Some(s) = self.flow_factor {
+            writeln!(out, "M221 S{}", s)?;
+        }
+        Ok(())
+    }
+}
+
+/// Cut the layers below a height out of a binary gcode file so a failed
+/// print can be resumed from the first layer at or above it. The start
+/// gcode is kept and followed by gcode restoring the temperatures, fans,
+/// extruder position and modes the printer had at that layer, then a move
+/// down onto the print.
+///
+/// Layers are found as in [`LayerIndex::from_binary`] so a layer number
+/// can be resumed from with its height. The metadata and thumbnails are
+/// copied through untouched along with the gcode blocks before the first
+/// layer and after the resume point. The gcode in between is replaced by
+/// new blocks using the compression and encoding of the block they
+/// replace.
+pub fn resume_from_layer(
+    binary: &[u8],
+    z: f32,
+) -> Result<Box<[u8]>, BinaryGcodeError> {
+    let index = LayerIndex::from_binary(binary)?;
+    let (Some(first), Some(resume)) = (index.layers.first(), index.find(z))
+    else {
+        return Err(BinaryGcodeError::NoLayer(z));
+    };
+    let (header, blocks) = deserialise_file(binary)?;
+
+    // Follow the state up to the start of the resumed layer.
+    let mut state = PrinterState::new();
+    let mut head = Vec::new();
+    let mut tail = Vec::new();
+    for (i, b) in blocks.iter().enumerate().take(resume.block_index + 1) {
+        if b.block.kind != BlockKind::GCode {
+            continue;
+        }
+        let gcode = b.block.gcode()?;
+        if i == first.block_index {
+            head.extend_from_slice(&gcode[..first.gcode_offset]);
+        }
+        if i == resume.block_index {
+            tail.extend_from_slice(&gcode[resume.gcode_offset..]);
+        }
+        for line in parse_gcode(&gcode) {
+            if i == resume.block_index && line.span.start >= resume.gcode_offset
+            {
+                break;
+            }
+            state.line(&line);
+        }
+    }
+
+    let restore = state.restore(resume.z);
+    let mut gcode = head;
+    if !gcode.is_empty() && !gcode.ends_with(b"\n") {
+        gcode.push(b'\n');
+    }
+    gcode.extend_from_slice(restore.as_bytes());
+    gcode.extend_from_slice(&tail);
+
+    let compression = blocks[first.block_index].block.compression;
+    let encoding = blocks[first.block_index].block.encoding;
+    let header_end = blocks.first().map_or(binary.len(), |b| b.range.start);
+    let mut result = Vec::with_capacity(binary.len());
+    result.extend_from_slice(&binary[..header_end]);
+    for (i, b) in blocks.iter().enumerate() {
+        let replaced = (first.block_index..=resume.block_index).contains(&i);
+        if !replaced || b.block.kind != BlockKind::GCode {
+            result.extend_from_slice(&binary[b.range.clone()]);
+        }
+        if i == resume.block_index {
+            for chunk in gcode_chunks(&gcode) {
+                let data = pack_gcode(encoding, chunk)?;
+                result.extend(serialise_block(
+                    BlockKind::GCode,
+                    compression,
+                    encoding,
+                    header.checksum,
+                    &[],
+                    &data,
+                )?);
+            }
+        }
+    }
+
+    // Keep a stored layer index pointing at the new blocks.
+    match LayerIndex::from_metadata(binary)? {
+        Some(_) => embed_layer_index(&result),
+        None => Ok(result.into_boxed_slice()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resume_from_layer;
+    use crate::binary_to_ascii;
+    use crate::components::common::{BinaryGcodeError, BlockKind};
+    use crate::components::deserialiser::{deserialise_file, FramedBlock};
+    use crate::components::layers::LayerIndex;
+    use crate::components::metadata::get_metadata;
+    use crate::components::thumbnail::thumbnails;
+
+    #[test]
+    fn resume_from_height() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let resumed = resume_from_layer(binary, 9.0).unwrap();
+
+        let before = LayerIndex::from_binary(binary).unwrap();
+        let after = LayerIndex::from_binary(&resumed).unwrap();
+        let skipped = before.layers.iter().filter(|l| l.z < 9.0).count();
+        assert_eq!(after.layers.len(), before.layers.len() - skipped);
+        assert_eq!(after.layers[0].z, before.layers[skipped].z);
+
+        // The start gcode is kept and the state restored.
+        let ascii = binary_to_ascii(&resumed, false).unwrap();
+        for line in ["G28\n", "; intro line\n", "M190 S85\n", "M109 S230\n"] {
+            assert!(ascii.contains(line), "{}", line);
+        }
+        let restore = &ascii[ascii.find(";RESUME").unwrap()..];
+        assert!(restore.contains("M83\n") && restore.contains("M106 S"));
+
+        // Everything else is copied through.
+        assert_eq!(thumbnails(&resumed).unwrap().len(), 2);
+        let kind = BlockKind::PrintMetadata;
+        assert_eq!(
+            get_metadata(binary, kind, "filament cost").unwrap(),
+            get_metadata(&resumed, kind, "filament cost").unwrap()
+        );
+        let (_, original) = deserialise_file(binary).unwrap();
+        let (_, blocks) = deserialise_file(&resumed).unwrap();
+        let same = |a: &FramedBlock, b: &FramedBlock| {
+            binary[a.range.clone()] == resumed[b.range.clone()]
+        };
+        let head = before.layers[0].block_index;
+        assert!(original
+            .iter()
+            .zip(&blocks)
+            .take(head)
+            .all(|(a, b)| same(a, b)));
+        let tail = original.len() - before.layers[skipped].block_index - 1;
+        let ends = original.iter().rev().zip(blocks.iter().rev());
+        assert!(ends.take(tail).all(|(a, b)| same(a, b)));
+
+        // The replaced gcode keeps the encoding of the original blocks.
+        let encoding = original[head].block.encoding;
+        assert!(blocks
+            .iter()
+            .filter(|b| b.block.kind == BlockKind::GCode)
+            .all(|b| b.block.encoding == encoding));
+
+        assert!(matches!(
+            resume_from_layer(binary, 100.0),
+            Err(BinaryGcodeError::NoLayer(_))
+        ));
+    }
+}
@@ -2,12 +2,14 @@ use alloc::{boxed::Box, vec::Vec};
 use embedded_heatshrink::{
     HSEFinishRes, HSEPollRes, HSESinkRes, HeatshrinkEncoder,
 };
+use meatpack::Packer;
 use miniz_oxide::deflate::compress_to_vec_zlib;
 
 use crate::components::common::{
     crc32, BinaryGcodeError, BlockKind, Checksum, CompressionAlgorithm,
     Encoding, MAGIC,
 };
+use crate::components::deserialiser::MEATPACK_LINE_LEN;
 
 pub fn serialise_file_header(
     version: u32,
@@ -20,6 +22,37 @@ pub fn serialise_file_header(
     header.into_boxed_slice()
 }
 
+/// The effort spent compressing a block, from fast for interactive
+/// previews to maximum for archival. Only Deflate has a tunable level.
+/// The heatshrink window and lookahead are fixed by the specification for
+/// `Heatshrink11_4` and `Heatshrink12_4` as a decoder has no other way of
+/// knowing them.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum CompressionLevel {
+    /// Fastest compression at the cost of size.
+    Fast,
+    /// A balance between compression speed and size.
+    Balanced,
+    /// The smallest output regardless of speed.
+    #[default]
+    Max,
+    /// A specific Deflate level between 0 (store) and 10.
+    Deflate(u8),
+}
+
+impl CompressionLevel {
+    /// Return the miniz Deflate level for the compression level.
+    pub const fn deflate_level(&self) -> u8 {
+        match *self {
+            CompressionLevel::Fast => 1,
+            CompressionLevel::Balanced => 6,
+            CompressionLevel::Max => 10,
+            CompressionLevel::Deflate(level) if level > 10 => 10,
+            CompressionLevel::Deflate(level) => level,
+        }
+    }
+}
+
 /// Serialise a gcode block.
 pub fn serialise_block(
     kind: BlockKind,
@@ -28,6 +61,27 @@ pub fn serialise_block(
     checksum: Checksum,
     additional_parameters: &[u8],
     data: &[u8],
+) -> Result<Box<[u8]>, BinaryGcodeError> {
+    serialise_block_with_level(
+        kind,
+        compression,
+        CompressionLevel::default(),
+        encoding,
+        checksum,
+        additional_parameters,
+        data,
+    )
+}
+
+/// Serialise a gcode block compressing it with the given effort.
+pub fn serialise_block_with_level(
+    kind: BlockKind,
+    compression: CompressionAlgorithm,
+    level: CompressionLevel,
+    encoding: Encoding,
+    checksum: Checksum,
+    additional_parameters: &[u8],
+    data: &[u8],
 ) -> Result<Box<[u8]>, BinaryGcodeError> {
     // Create the block header
     let mut block: Vec<u8> = Vec::new();
@@ -50,7 +104,7 @@ pub fn serialise_block(
             block.extend(data);
         }
         CompressionAlgorithm::Deflate => {
-            let compressed = compress_to_vec_zlib(data, 10);
+            let compressed = compress_to_vec_zlib(data, level.deflate_level());
             let compressed_len = compressed.len() as u32;
             block.extend(compressed_len.to_le_bytes());
             block.extend(parameters);
@@ -81,6 +135,111 @@ pub fn serialise_block(
     Ok(block.into_boxed_slice())
 }
 
+/// Encode the ascii gcode of a gcode block, packing it with meatpack for
+/// the meatpack encodings. Plain meatpack drops the comments.
+pub(crate) fn pack_gcode(
+    encoding: Encoding,
+    gcode: &[u8],
+) -> Result<Vec<u8>, BinaryGcodeError> {
+    match encoding {
+        Encoding::Meatpack | Encoding::MeatpackWithComments => {
+            if gcode.is_empty() {
+                return Ok(Vec::new());
+            }
+            // A line that does not pack into half the buffer may not fit.
+            let lines = gcode.split(|&b| b == b'\n');
+            if lines.into_iter().any(|l| l.len() * 2 >= MEATPACK_LINE_LEN) {
+                return Err(BinaryGcodeError::SerialiseError("meatpack_line"));
+            }
+            let mut packed = Vec::with_capacity(gcode.len());
+            let strip_comments = encoding == Encoding::Meatpack;
+            let mut reader = gcode;
+            Packer::new(strip_comments, false)
+                .pack(&mut reader, &mut packed)?;
+            Ok(packed)
+        }
+        _ => Ok(gcode.to_vec()),
+    }
+}
+
+/// The statistics of a block serialised by `serialise_block_auto` or
+/// `ascii_to_binary_with_statistics`.
+#[derive(Debug, Clone)]
+pub struct BlockStatistics {
+    pub kind: BlockKind,
+    /// The compression algorithm of the block, the one that produced the
+    /// smallest block when several were tried.
+    pub compression: CompressionAlgorithm,
+    pub uncompressed_len: usize,
+    /// The length of the chosen block including its header and checksum.
+    pub block_len: usize,
+    /// The block length produced by each algorithm that was tried.
+    pub candidates: Vec<(CompressionAlgorithm, usize)>,
+}
+
+impl BlockStatistics {
+    /// The size of the chosen block relative to the uncompressed data.
+    pub fn ratio(&self) -> f64 {
+        if self.uncompressed_len == 0 {
+            return 1.0;
+        }
+        self.block_len as f64 / self.uncompressed_len as f64
+    }
+}
+
+/// Serialise a gcode block trying each of the allowed compression
+/// algorithms and keeping whichever produces the smallest block. All the
+/// algorithms in the specification are tried if no allow-list is provided,
+/// e.g. a low-RAM printer may pass `&[None, Heatshrink11_4, Heatshrink12_4]`
+/// to exclude Deflate. Ties are resolved in favour of the algorithm that
+/// appears first in the list.
+pub fn serialise_block_auto(
+    kind: BlockKind,
+    allowed: Option<&[CompressionAlgorithm]>,
+    level: CompressionLevel,
+    encoding: Encoding,
+    checksum: Checksum,
+    additional_parameters: &[u8],
+    data: &[u8],
+) -> Result<(Box<[u8]>, BlockStatistics), BinaryGcodeError> {
+    let allowed = allowed.unwrap_or(&CompressionAlgorithm::ALL);
+    let mut best: Option<(Box<[u8]>, CompressionAlgorithm)> = None;
+    let mut candidates = Vec::with_capacity(allowed.len());
+
+    for compression in allowed {
+        let block = serialise_block_with_level(
+            kind,
+            *compression,
+            level,
+            encoding,
+            checksum,
+            additional_parameters,
+            data,
+        )?;
+        candidates.push((*compression, block.len()));
+        let smaller = match &best {
+            Some((b, _)) => block.len() < b.len(),
+            None => true,
+        };
+        if smaller {
+            best = Some((block, *compression));
+        }
+    }
+
+    let Some((block, compression)) = best else {
+        return Err(BinaryGcodeError::SerialiseError("auto_empty_allow_list"));
+    };
+
+    let stats = BlockStatistics {
+        kind,
+        compression,
+        uncompressed_len: data.len(),
+        block_len: block.len(),
+        candidates,
+    };
+    Ok((block, stats))
+}
+
 /// A wrapper around the heatshrink algorithm that can be
 /// used to compress gcode.
 /// TODO: add a check to limit the size of the input slice.
@@ -94,7 +253,8 @@ fn shrink(
     let mut sunk: usize = 0;
     let mut polled: usize = 0;
 
-    // Should never be as big as the input
+    // Gcode should never be as big as the input but already compressed
+    // data (e.g. thumbnails) can grow so the buffer is extended on demand.
     let mut output = vec![0u8; input.len()];
 
     // Keep looping until we have sunk all the input data
@@ -117,6 +277,11 @@ fn shrink(
                         break;
                     }
                 }
+                // The output buffer is full.
+                HSEPollRes::More(sz) => {
+                    polled += sz;
+                    output.resize(output.len() * 2 + 64, 0u8);
+                }
                 _ => {
                     return Err(BinaryGcodeError::SerialiseError(
                         "heatshrink_02",
@@ -134,6 +299,10 @@ fn shrink(
                 // Through my trials. Only this was ever called
                 // in our scenario.
                 HSEPollRes::Empty(sz) => polled += sz,
+                HSEPollRes::More(sz) => {
+                    polled += sz;
+                    output.resize(output.len() * 2 + 64, 0u8);
+                }
                 _ => {
                     return Err(BinaryGcodeError::SerialiseError(
                         "heatshrink_03",
@@ -239,4 +408,96 @@ mod test {
             }
         }
     }
+
+    #[test]
+    pub fn serialise_deflate_levels() {
+        let gcode = "G1 X10.123 Y20.456 E0.789\nG1 X12 Y3 F1200\n".repeat(500);
+        let mut sizes = Vec::new();
+        for level in [
+            CompressionLevel::Deflate(0),
+            CompressionLevel::Fast,
+            CompressionLevel::Max,
+        ] {
+            let block = serialise_block_with_level(
+                BlockKind::GCode,
+                CompressionAlgorithm::Deflate,
+                level,
+                Encoding::Ascii,
+                Checksum::Crc32,
+                &[],
+                gcode.as_bytes(),
+            )
+            .unwrap();
+            sizes.push(block.len());
+        }
+        // Level 0 only stores the data.
+        assert!(sizes[0] > gcode.len());
+        assert!(sizes[1] >= sizes[2]);
+    }
+
+    #[test]
+    pub fn serde_gcode_auto() {
+        let header = serialise_file_header(1, Checksum::Crc32);
+        let gcode = "G1 X10 Y10 E1\n".repeat(200);
+        let (block, stats) = serialise_block_auto(
+            BlockKind::GCode,
+            None,
+            CompressionLevel::Max,
+            Encoding::Ascii,
+            Checksum::Crc32,
+            &[],
+            gcode.as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(stats.candidates.len(), 4);
+        assert_ne!(stats.compression, CompressionAlgorithm::None);
+        assert_eq!(stats.block_len, block.len());
+        assert!(stats.candidates.iter().all(|(_, len)| *len >= block.len()));
+
+        let mut deserialiser = Deserialiser::default();
+        deserialiser.digest(&header);
+        deserialiser.digest(&block);
+        let mut decoded = None;
+        loop {
+            match deserialiser.deserialise().unwrap() {
+                DeserialisedResult::FileHeader(_) => {}
+                DeserialisedResult::Block(b) => decoded = Some(b),
+                DeserialisedResult::MoreBytesRequired(_) => break,
+            }
+        }
+        let decoded = decoded.unwrap();
+        assert_eq!(decoded.compression, stats.compression);
+        assert_eq!(decoded.decompress().unwrap().as_ref(), gcode.as_bytes());
+    }
+
+    #[test]
+    pub fn serialise_auto_allow_list() {
+        // Random-ish bytes do not compress so None should win even when
+        // heatshrink output grows beyond the input.
+        let mut state = 0x2545f491u32;
+        let data: Vec<u8> = (0..512)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let allowed = [
+            CompressionAlgorithm::Heatshrink11_4,
+            CompressionAlgorithm::None,
+        ];
+        let (_, stats) = serialise_block_auto(
+            BlockKind::Thumbnail,
+            Some(&allowed),
+            CompressionLevel::Fast,
+            Encoding::Png,
+            Checksum::Crc32,
+            &[16, 0, 16, 0],
+            &data,
+        )
+        .unwrap();
+        assert_eq!(stats.compression, CompressionAlgorithm::None);
+        assert_eq!(stats.candidates.len(), 2);
+    }
 }
@@ -0,0 +1,363 @@
+use alloc::vec::Vec;
+use core::f64::consts::{PI, TAU};
+
+use crate::components::common::{BinaryGcodeError, BlockKind};
+use crate::components::deserialiser::deserialise_file;
+use crate::components::gcode::{parse_gcode, Arc, Command, GcodeLine, Move};
+use crate::components::layers::LayerDetector;
+use crate::components::math::{atan2, sqrt};
+use crate::components::metadata::parse_metadata;
+
+/// The filament diameter assumed when the file does not give one.
+const DEFAULT_FILAMENT_DIAMETER: f64 = 1.75;
+
+/// The filament used by an extruder.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct FilamentUsage {
+    /// The length of filament extruded while moving in mm.
+    pub length: f64,
+    /// The volume of filament in mm³.
+    pub volume: f64,
+}
+
+/// Statistics computed from the gcode of a print.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct PrintStatistics {
+    /// The filament used by each extruder indexed by tool number.
+    pub filament: Vec<FilamentUsage>,
+    /// The distance moved while extruding in mm.
+    pub extrusion_distance: f64,
+    /// The distance moved without extruding in mm.
+    pub travel_distance: f64,
+    /// The minimum and maximum corners of the extruding moves.
+    pub bounding_box: Option<[[f64; 3]; 2]>,
+    pub layer_count: usize,
+    /// The highest extruding move.
+    pub max_z: f64,
+}
+
+impl PrintStatistics {
+    /// Compute the statistics of some ascii gcode. The filament diameters
+    /// are given per extruder and extrusion starts out absolute unless
+    /// `relative_e` is set.
+    pub fn from_gcode(
+        gcode: &[u8],
+        filament_diameters: &[f64],
+        relative_e: bool,
+    ) -> Self {
+        let mut builder = StatisticsBuilder::new(relative_e);
+        for line in parse_gcode(gcode) {
+            builder.line(&line);
+        }
+        builder.finish(filament_diameters)
+    }
+
+    /// Compute the statistics of the gcode blocks in a binary gcode file.
+    /// The filament diameters and `use_relative_e_distances` are read from
+    /// the slicer metadata when it has them.
+    pub fn from_binary(binary: &[u8]) -> Result<Self, BinaryGcodeError> {
+        let (_, blocks) = deserialise_file(binary)?;
+        let mut diameters = Vec::new();
+        let mut relative_e = false;
+        if let Some(b) = blocks
+            .iter()
+            .find(|b| b.block.kind == BlockKind::SlicerMetadata)
+        {
+            let data = b.block.decompress()?;
+            for (key, value) in parse_metadata(&data)? {
+                match key {
+                    "filament_diameter" => {
+                        diameters = value
+                            .split(',')
+                            .filter_map(|d| d.trim().parse().ok())
+                            .collect();
+                    }
+                    "use_relative_e_distances" => relative_e = value == "1",
+                    _ => {}
+                }
+            }
+        }
+
+        let mut builder = StatisticsBuilder::new(relative_e);
+        for b in blocks.iter().filter(|b| b.block.kind == BlockKind::GCode) {
+            for line in parse_gcode(&b.block.gcode()?) {
+                builder.line(&line);
+            }
+        }
+        Ok(builder.finish(&diameters))
+    }
+}
+
+/// Follows the machine state through the gcode adding up each move.
+struct StatisticsBuilder {
+    position: [f64; 4],
+    absolute: bool,
+    absolute_e: bool,
+    tool: usize,
+    /// The filament length of each tool.
+    filament: Vec<f64>,
+    extrusion_distance: f64,
+    travel_distance: f64,
+    bounding_box: Option<[[f64; 3]; 2]>,
+    layers: LayerDetector,
+    lines: usize,
+}
+
+impl StatisticsBuilder {
+    fn new(relative_e: bool) -> Self {
+        Self {
+            position: [0.0; 4],
+            absolute: true,
+            absolute_e: !relative_e,
+            tool: 0,
+            filament: Vec::new(),
+            extrusion_distance: 0.0,
+            travel_distance: 0.0,
+            bounding_box: None,
+            layers: LayerDetector::new(relative_e),
+            lines: 0,
+        }
+    }
+
+    fn line(
+        &mut self,
+        line: &GcodeLine,
+    ) {
+        self.layers.line(line, self.lines);
+        self.lines += 1;
+        match &line.command {
+            Some(Command::G0(m) | Command::G1(m)) => {
+                let to = self.target(m);
+                let distance = distance(&self.position, &to);
+                self.travel(to, distance);
+            }
+            Some(Command::G2(a)) => self.arc(a, true),
+            Some(Command::G3(a)) => self.arc(a, false),
+            Some(Command::G90) => {
+                self.absolute = true;
+                self.absolute_e = true;
+            }
+            Some(Command::G91) => {
+                self.absolute = false;
+                self.absolute_e = false;
+            }
+            Some(Command::M82) => self.absolute_e = true,
+            Some(Command::M83) => self.absolute_e = false,
+            Some(Command::G92(axes)) => {
+                let values = [axes.x, axes.y, axes.z, axes.e];
+                for (axis, value) in values.into_iter().enumerate() {
+                    if let Some(v) = value {
+                        self.position[axis] = v as f64;
+                    }
+                }
+            }
+            Some(Command::T(tool)) => self.tool = *tool as usize,
+            _ => {}
+        }
+    }
+
+    /// Return where a move ends following the positioning modes.
+    fn target(
+        &self,
+        m: &Move,
+    ) -> [f64; 4] {
+        move_target(&self.position, m, self.absolute, self.absolute_e)
+    }
+
+    fn arc(
+        &mut self,
+        a: &Arc,
+        clockwise: bool,
+    ) {
+        let m = Move {
+            x: a.x,
+            y: a.y,
+            z: a.z,
+            e: a.e,
+            f: a.f,
+        };
+        let to = self.target(&m);
+        let distance = arc_length(&self.position, &to, a, clockwise);
+        self.travel(to, distance);
+    }
+
+    /// Move to a position adding the distance to the extrusion or travel
+    /// totals.
+    fn travel(
+        &mut self,
+        to: [f64; 4],
+        distance: f64,
+    ) {
+        let from = self.position;
+        let extruded = to[3] - from[3];
+        if self.filament.len() <= self.tool {
+            self.filament.resize(self.tool + 1, 0.0);
+        }
+
+        // Like the slicers only filament pushed out while moving counts so
+        // retractions and the matching deretractions are left out.
+        if extruded > 0.0 && distance > 0.0 {
+            self.filament[self.tool] += extruded;
+            self.extrusion_distance += distance;
+            let [min, max] = self.bounding_box.get_or_insert([
+                [from[0], from[1], from[2]],
+                [from[0], from[1], from[2]],
+            ]);
+            for point in [from, to] {
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(point[axis]);
+                    max[axis] = max[axis].max(point[axis]);
+                }
+            }
+        } else {
+            self.travel_distance += distance;
+        }
+        self.position = to;
+    }
+
+    fn finish(
+        self,
+        filament_diameters: &[f64],
+    ) -> PrintStatistics {
+        let filament = self
+            .filament
+            .iter()
+            .enumerate()
+            .map(|(tool, length)| {
+                let diameter = filament_diameters
+                    .get(tool)
+                    .or(filament_diameters.last())
+                    .copied()
+                    .unwrap_or(DEFAULT_FILAMENT_DIAMETER);
+                FilamentUsage {
+                    length: *length,
+                    volume: length * PI * diameter * diameter / 4.0,
+                }
+            })
+            .collect();
+        PrintStatistics {
+            filament,
+            extrusion_distance: self.extrusion_distance,
+            travel_distance: self.travel_distance,
+            bounding_box: self.bounding_box,
+            layer_count: self.layers.finish().len(),
+            max_z: self.bounding_box.map_or(0.0, |[_, max]| max[2]),
+        }
+    }
+}
+
+/// Return where a move from a position ends following the positioning
+/// modes of the xyz axes and the extruder.
+pub(crate) fn move_target(
+    from: &[f64; 4],
+    m: &Move,
+    absolute: bool,
+    absolute_e: bool,
+) -> [f64; 4] {
+    let mut to = *from;
+    for (axis, value) in [m.x, m.y, m.z, m.e].into_iter().enumerate() {
+        let Some(value) = value else {
+            continue;
+        };
+        let absolute = match axis {
+            3 => absolute_e,
+            _ => absolute,
+        };
+        let value = value as f64;
+        to[axis] = if absolute { value } else { from[axis] + value };
+    }
+    to
+}
+
+/// Return the straight line distance between two positions.
+pub(crate) fn distance(
+    from: &[f64; 4],
+    to: &[f64; 4],
+) -> f64 {
+    let d = [to[0] - from[0], to[1] - from[1], to[2] - from[2]];
+    sqrt(d[0] * d[0] + d[1] * d[1] + d[2] * d[2])
+}
+
+/// Return the length of an arc move given by its centre offset or its
+/// radius, including any change in height.
+pub(crate) fn arc_length(
+    from: &[f64; 4],
+    to: &[f64; 4],
+    arc: &Arc,
+    clockwise: bool,
+) -> f64 {
+    let d = [to[0] - from[0], to[1] - from[1]];
+    let chord = sqrt(d[0] * d[0] + d[1] * d[1]);
+    let (radius, sweep) = match (arc.i, arc.j, arc.r) {
+        (None, None, Some(r)) => {
+            let r = r.abs() as f64;
+            if r == 0.0 || chord > 2.0 * r {
+                return distance(from, to);
+            }
+            // A negative radius takes the long way round.
+            let half = chord / (2.0 * r);
+            let sweep = 2.0 * atan2(half, sqrt(1.0 - half * half));
+            let sweep = if arc.r.is_some_and(|r| r < 0.0) {
+                TAU - sweep
+            } else {
+                sweep
+            };
+            (r, sweep)
+        }
+        (i, j, _) => {
+            let centre = [
+                from[0] + i.unwrap_or(0.0) as f64,
+                from[1] + j.unwrap_or(0.0) as f64,
+            ];
+            let start = atan2(from[1] - centre[1], from[0] - centre[0]);
+            let end = atan2(to[1] - centre[1], to[0] - centre[0]);
+            let mut sweep = if clockwise { start - end } else { end - start };
+            // Ending where it starts is a full circle.
+            if sweep <= 0.0 {
+                sweep += TAU;
+            }
+            let r = [from[0] - centre[0], from[1] - centre[1]];
+            let radius = sqrt(r[0] * r[0] + r[1] * r[1]);
+            (radius, sweep)
+        }
+    };
+    let planar = radius * sweep;
+    let dz = to[2] - from[2];
+    sqrt(planar * planar + dz * dz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrintStatistics;
+
+    #[test]
+    fn statistics_from_file() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let stats = PrintStatistics::from_binary(binary).unwrap();
+        // The slicer reports 986.61 mm and 2.37 cm³.
+        assert_eq!(stats.filament.len(), 1);
+        assert!((stats.filament[0].length - 986.61).abs() < 0.01);
+        assert!((stats.filament[0].volume / 1000.0 - 2.37).abs() < 0.01);
+        assert_eq!(stats.layer_count, 120);
+        assert!((stats.max_z - 18.05).abs() < 1e-6);
+        let [min, max] = stats.bounding_box.unwrap();
+        assert!(max[0] - min[0] > 20.0 && max[1] - min[1] > 20.0);
+        assert!(stats.extrusion_distance > stats.travel_distance);
+    }
+
+    #[test]
+    fn relative_and_arc_moves() {
+        let gcode = b"G21\nM83\nG1 X10 Y0 Z0.2 F600\nG1 X20 E1\nG1 E-0.5\nG1 X30\nG1 E0.5\nG3 X30 Y10 I0 J5 E2\nT1\nG1 X0 Y10 E3\n";
+        let stats = PrintStatistics::from_gcode(gcode, &[1.75, 2.85], false);
+        assert_eq!(stats.filament.len(), 2);
+        assert!((stats.filament[0].length - 3.0).abs() < 1e-9);
+        assert!((stats.filament[1].length - 3.0).abs() < 1e-9);
+        let half_circle = core::f64::consts::PI * 5.0;
+        assert!(
+            (stats.extrusion_distance - (10.0 + half_circle + 30.0)).abs()
+                < 1e-6
+        );
+        let travel = super::distance(&[0.0; 4], &[10.0, 0.0, 0.2, 0.0]) + 10.0;
+        assert!((stats.travel_distance - travel).abs() < 1e-4);
+    }
+}
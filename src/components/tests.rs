@@ -1,4 +1,14 @@
-use crate::components::deserialiser::{DeserialisedResult, Deserialiser};
+use crate::components::common::{
+    crc32, BlockKind, Checksum, CompressionAlgorithm, Encoding,
+};
+use crate::components::convert::{ascii_to_binary, binary_to_ascii};
+use crate::components::deserialiser::{
+    unpack_gcode, DeserialisedResult, Deserialiser,
+};
+use crate::components::serialiser::{
+    pack_gcode, serialise_block, serialise_file_header,
+};
+use alloc::vec::Vec;
 
 // TODO: Make some more robust tests.
 #[test]
@@ -16,3 +26,114 @@ fn deser_test_file() {
         }
     }
 }
+
+// Guards the conversions against changes in their output, e.g. the
+// parallel feature must produce the same bytes as the sequential path.
+#[test]
+fn convert_output_is_stable() {
+    let gcode = include_str!("../../test_files/mini_cube_b.gcode");
+    let binary = ascii_to_binary(gcode).unwrap();
+    assert_eq!(binary.len(), 208735);
+    assert_eq!(crc32(&binary), 0xa8a650b1);
+
+    let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+    let gcode = binary_to_ascii(binary, true).unwrap();
+    assert_eq!(gcode.len(), 575571);
+    assert_eq!(crc32(gcode.as_bytes()), 0xa79b9a4f);
+}
+
+/// Deserialise all the blocks in the buffer returning their data.
+fn deserialise_blocks(
+    deserialiser: &mut Deserialiser,
+    blocks: &mut Vec<Vec<u8>>,
+) {
+    loop {
+        match deserialiser.deserialise().unwrap() {
+            DeserialisedResult::FileHeader(_) => {}
+            DeserialisedResult::Block(b) => blocks.push(b.data.to_vec()),
+            DeserialisedResult::MoreBytesRequired(_) => break,
+        }
+    }
+}
+
+#[test]
+fn deser_test_file_in_chunks() {
+    let binary = include_bytes!("../../test_files/mini_cube_ps2.8.1.bgcode");
+
+    let mut whole = Vec::new();
+    let mut deserialiser = Deserialiser::default();
+    deserialiser.digest(binary);
+    deserialise_blocks(&mut deserialiser, &mut whole);
+    assert!(deserialiser.remaining().is_empty());
+
+    for size in [1, 7, 256, 4096] {
+        let mut chunked = Vec::new();
+        let mut deserialiser = Deserialiser::default();
+        for chunk in binary.chunks(size) {
+            deserialiser.digest(chunk);
+            deserialise_blocks(&mut deserialiser, &mut chunked);
+        }
+        assert!(deserialiser.remaining().is_empty());
+        assert_eq!(whole, chunked);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn deser_from_reader() {
+    let binary = include_bytes!("../../test_files/mini_cube_ps2.8.1.bgcode");
+
+    let mut whole = Vec::new();
+    let mut deserialiser = Deserialiser::default();
+    deserialiser.digest(binary);
+    deserialise_blocks(&mut deserialiser, &mut whole);
+
+    let mut read = Vec::new();
+    let mut reader = std::io::Cursor::new(binary);
+    let mut deserialiser = Deserialiser::default();
+    while deserialiser.read_from(&mut reader).unwrap() > 0 {
+        deserialise_blocks(&mut deserialiser, &mut read);
+    }
+    assert!(deserialiser.remaining().is_empty());
+    assert_eq!(whole, read);
+}
+
+#[test]
+fn deser_without_checksum() {
+    let header = serialise_file_header(1, Checksum::None);
+    let block = serialise_block(
+        BlockKind::GCode,
+        CompressionAlgorithm::None,
+        Encoding::Ascii,
+        Checksum::None,
+        &[],
+        b"G28",
+    )
+    .unwrap();
+    let mut deserialiser = Deserialiser::default();
+    deserialiser.digest(&header);
+    deserialiser.digest(&block);
+    let mut blocks = Vec::new();
+    deserialise_blocks(&mut deserialiser, &mut blocks);
+    assert_eq!(blocks, [b"G28".to_vec()]);
+}
+
+#[test]
+fn meatpack_padding_is_dropped() {
+    // G90 padded with a pair of line feeds, as written by PrusaSlicer.
+    let mut gcode = Vec::new();
+    unpack_gcode(Encoding::Meatpack, b"\xff\xff\xfb\x9d\xc0\xcc", &mut gcode)
+        .unwrap();
+    assert_eq!(gcode, b"G90\n");
+
+    // Lines with an odd length end in the pair.
+    let packed = pack_gcode(Encoding::Meatpack, b"G1\nG90\n").unwrap();
+    let mut gcode = Vec::new();
+    unpack_gcode(Encoding::Meatpack, &packed, &mut gcode).unwrap();
+    assert_eq!(gcode, b"G1\nG90\n");
+
+    // Blank lines before packing is enabled are kept.
+    let mut gcode = Vec::new();
+    unpack_gcode(Encoding::Meatpack, b"G1 X1\n\nG1 X2\n", &mut gcode).unwrap();
+    assert_eq!(gcode, b"G1 X1\n\nG1 X2\n");
+}
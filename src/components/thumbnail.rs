@@ -0,0 +1,881 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{fmt, ops::Range};
+
+use crate::components::common::{
+    BinaryGcodeError, BlockKind, Checksum, CompressionAlgorithm, Encoding,
+};
+use crate::components::deserialiser::{
+    deserialise_file, try_from_slice, DeserialisedBlock, DeserialisedResult,
+    Deserialiser,
+};
+use crate::components::qoi;
+use crate::components::serialiser::serialise_block;
+
+/// The image formats a thumbnail can be stored in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ThumbnailFormat {
+    Png,
+    Jpg,
+    Qoi,
+}
+
+impl ThumbnailFormat {
+    /// Return the thumbnail format of a thumbnail block encoding.
+    pub const fn from_encoding(
+        encoding: Encoding
+    ) -> Result<Self, BinaryGcodeError> {
+        match encoding {
+            Encoding::Png => Ok(ThumbnailFormat::Png),
+            Encoding::Jpg => Ok(ThumbnailFormat::Jpg),
+            Encoding::Qoi => Ok(ThumbnailFormat::Qoi),
+            _ => Err(BinaryGcodeError::InvalidThumbnail("encoding")),
+        }
+    }
+
+    /// Return the block encoding of the thumbnail format.
+    pub const fn encoding(&self) -> Encoding {
+        match *self {
+            ThumbnailFormat::Png => Encoding::Png,
+            ThumbnailFormat::Jpg => Encoding::Jpg,
+            ThumbnailFormat::Qoi => Encoding::Qoi,
+        }
+    }
+}
+
+const PNG_SIGNATURE: [u8; 8] =
+    [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The format and dimensions of an image.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ImageInfo {
+    pub format: ThumbnailFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for ImageInfo {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{:?} {}x{}", self.format, self.width, self.height)
+    }
+}
+
+impl ImageInfo {
+    /// Read the format and dimensions of an encoded image from its PNG
+    /// IHDR chunk, JPEG start of frame segment or QOI header without
+    /// decoding the pixels.
+    pub fn from_image(data: &[u8]) -> Result<Self, BinaryGcodeError> {
+        if data.starts_with(&PNG_SIGNATURE) {
+            png_info(data)
+        } else if data.starts_with(&[0xFF, 0xD8]) {
+            jpg_info(data)
+        } else if data.starts_with(b"qoif") {
+            let header = qoi::header(data)?;
+            Ok(Self {
+                format: ThumbnailFormat::Qoi,
+                width: header.width,
+                height: header.height,
+            })
+        } else {
+            Err(BinaryGcodeError::InvalidThumbnail("unknown_format"))
+        }
+    }
+}
+
+fn png_info(data: &[u8]) -> Result<ImageInfo, BinaryGcodeError> {
+    // The IHDR chunk must come first straight after the signature.
+    let ihdr = data
+        .get(8..24)
+        .filter(|c| c[4..8] == *b"IHDR")
+        .ok_or(BinaryGcodeError::InvalidThumbnail("png_ihdr"))?;
+    let width = u32::from_be_bytes(try_from_slice::<4>(&ihdr[8..12])?);
+    let height = u32::from_be_bytes(try_from_slice::<4>(&ihdr[12..16])?);
+    Ok(ImageInfo {
+        format: ThumbnailFormat::Png,
+        width,
+        height,
+    })
+}
+
+fn jpg_info(data: &[u8]) -> Result<ImageInfo, BinaryGcodeError> {
+    let err = || BinaryGcodeError::InvalidThumbnail("jpg_sof");
+    let mut i = 2;
+    loop {
+        // Markers may be padded with any number of fill bytes.
+        if *data.get(i).ok_or_else(err)? != 0xFF {
+            return Err(err());
+        }
+        while data.get(i) == Some(&0xFF) {
+            i += 1;
+        }
+        let marker = *data.get(i).ok_or_else(err)?;
+        i += 1;
+        match marker {
+            // Standalone markers without a length.
+            0x01 | 0xD0..=0xD7 => continue,
+            // Huffman, arithmetic coding and the reserved JPG marker
+            // share the start of frame range.
+            0xC4 | 0xC8 | 0xCC => {}
+            0xC0..=0xCF => {
+                let sof = data.get(i + 2..i + 7).ok_or_else(err)?;
+                return Ok(ImageInfo {
+                    format: ThumbnailFormat::Jpg,
+                    width: u16::from_be_bytes([sof[3], sof[4]]) as u32,
+                    height: u16::from_be_bytes([sof[1], sof[2]]) as u32,
+                });
+            }
+            // The image data started or ended without a frame.
+            0xD9 | 0xDA => return Err(err()),
+            _ => {}
+        }
+        let len = data.get(i..i + 2).ok_or_else(err)?;
+        i += u16::from_be_bytes([len[0], len[1]]) as usize;
+    }
+}
+
+/// A thumbnail image stored in a thumbnail block.
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub format: ThumbnailFormat,
+    pub width: u16,
+    pub height: u16,
+    /// The encoded image.
+    pub data: Box<[u8]>,
+}
+
+impl Thumbnail {
+    /// Create a thumbnail from a deserialised thumbnail block,
+    /// decompressing the image data if required. The parameters are taken
+    /// as declared, use [`Thumbnail::validate`] to check them against the
+    /// image.
+    pub fn from_block(
+        block: &DeserialisedBlock
+    ) -> Result<Self, BinaryGcodeError> {
+        let data = block.decompress()?;
+        Self::from_data(block, data)
+    }
+
+    /// Create a thumbnail from a deserialised thumbnail block as
+    /// [`Thumbnail::from_block`] does, rejecting it unless it passes
+    /// [`Thumbnail::validate_payload`].
+    pub fn from_block_checked(
+        block: &DeserialisedBlock
+    ) -> Result<Self, BinaryGcodeError> {
+        let thumbnail = Self::from_block(block)?;
+        thumbnail.validate_payload()?;
+        Ok(thumbnail)
+    }
+
+    /// Create a thumbnail from a thumbnail block and its already
+    /// decompressed image data.
+    pub(crate) fn from_data(
+        block: &DeserialisedBlock,
+        data: Box<[u8]>,
+    ) -> Result<Self, BinaryGcodeError> {
+        if block.kind != BlockKind::Thumbnail || block.parameters.len() < 6 {
+            return Err(BinaryGcodeError::InvalidThumbnail("block"));
+        }
+        let format = ThumbnailFormat::from_encoding(block.encoding)?;
+        let width = try_from_slice::<2>(&block.parameters[2..=3])?;
+        let width = u16::from_le_bytes(width);
+        let height = try_from_slice::<2>(&block.parameters[4..=5])?;
+        let height = u16::from_le_bytes(height);
+        Ok(Self {
+            format,
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Check the declared format and dimensions match the image header.
+    pub fn validate(&self) -> Result<(), BinaryGcodeError> {
+        let declared = ImageInfo {
+            format: self.format,
+            width: self.width as u32,
+            height: self.height as u32,
+        };
+        let actual = ImageInfo::from_image(&self.data)?;
+        if declared != actual {
+            return Err(BinaryGcodeError::ThumbnailMismatch {
+                declared,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the declared format and dimensions as [`Thumbnail::validate`]
+    /// does, and that a QOI image decodes in full. PNG and JPG images are
+    /// only checked as far as their headers.
+    pub fn validate_payload(&self) -> Result<(), BinaryGcodeError> {
+        self.validate()?;
+        if self.format == ThumbnailFormat::Qoi {
+            qoi::decode(&self.data)?;
+        }
+        Ok(())
+    }
+
+    /// Create a thumbnail by resizing an image to the given dimensions and
+    /// encoding it in the given format.
+    pub fn from_image(
+        image: &RgbaImage,
+        format: ThumbnailFormat,
+        width: u16,
+        height: u16,
+    ) -> Result<Self, BinaryGcodeError> {
+        let data = image.resize(width as u32, height as u32)?.encode(format)?;
+        Ok(Self {
+            format,
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Return the parameters of a thumbnail block beyond the encoding.
+    pub fn additional_parameters(&self) -> [u8; 4] {
+        let [w0, w1] = self.width.to_le_bytes();
+        let [h0, h1] = self.height.to_le_bytes();
+        [w0, w1, h0, h1]
+    }
+
+    /// Decode the thumbnail into an RGBA pixel buffer. PNG and JPG
+    /// thumbnails require the `images` feature.
+    pub fn decode(&self) -> Result<RgbaImage, BinaryGcodeError> {
+        RgbaImage::decode(self.format, &self.data)
+    }
+}
+
+/// A decoded image with 8-bit RGBA pixels stored row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Decode an image in the given format. PNG and JPG images require
+    /// the `images` feature.
+    pub fn decode(
+        format: ThumbnailFormat,
+        data: &[u8],
+    ) -> Result<Self, BinaryGcodeError> {
+        match format {
+            ThumbnailFormat::Qoi => qoi::decode(data),
+            #[cfg(feature = "images")]
+            ThumbnailFormat::Png => decode_png(data),
+            #[cfg(feature = "images")]
+            ThumbnailFormat::Jpg => decode_jpg(data),
+            #[cfg(not(feature = "images"))]
+            _ => Err(BinaryGcodeError::ImageError(format!(
+                "{:?} decoding requires the images feature",
+                format
+            ))),
+        }
+    }
+
+    /// Resize the image with a triangle filter that is widened when
+    /// shrinking so every source pixel contributes. Colours are weighted
+    /// by their alpha so transparent pixels do not bleed into the edges.
+    pub fn resize(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, BinaryGcodeError> {
+        let (sw, sh) = (self.width as usize, self.height as usize);
+        if width == 0
+            || height == 0
+            || sw == 0
+            || sh == 0
+            || self.pixels.len() != sw * sh * 4
+        {
+            return Err(BinaryGcodeError::InvalidThumbnail("dimensions"));
+        }
+        if width == self.width && height == self.height {
+            return Ok(self.clone());
+        }
+        let (dw, dh) = (width as usize, height as usize);
+
+        let src: Vec<f32> = self
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let a = p[3] as f32 / 255.0;
+                [
+                    p[0] as f32 * a,
+                    p[1] as f32 * a,
+                    p[2] as f32 * a,
+                    p[3] as f32,
+                ]
+            })
+            .collect();
+
+        // Resize the rows then the columns.
+        let mut rows = vec![0f32; dw * sh * 4];
+        let xs = filter_weights(sw, dw);
+        for y in 0..sh {
+            for (x, (start, weights)) in xs.iter().enumerate() {
+                let out = (y * dw + x) * 4;
+                for (i, w) in weights.iter().enumerate() {
+                    let inp = (y * sw + start + i) * 4;
+                    for c in 0..4 {
+                        rows[out + c] += src[inp + c] * w;
+                    }
+                }
+            }
+        }
+        let mut resized = vec![0f32; dw * dh * 4];
+        let ys = filter_weights(sh, dh);
+        for (y, (start, weights)) in ys.iter().enumerate() {
+            for x in 0..dw {
+                let out = (y * dw + x) * 4;
+                for (i, w) in weights.iter().enumerate() {
+                    let inp = ((start + i) * dw + x) * 4;
+                    for c in 0..4 {
+                        resized[out + c] += rows[inp + c] * w;
+                    }
+                }
+            }
+        }
+
+        let to_u8 = |v: f32| (v.clamp(0.0, 255.0) + 0.5) as u8;
+        let pixels = resized
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let a = p[3].clamp(0.0, 255.0);
+                if a == 0.0 {
+                    return [0, 0, 0, 0];
+                }
+                let f = 255.0 / a;
+                [to_u8(p[0] * f), to_u8(p[1] * f), to_u8(p[2] * f), to_u8(a)]
+            })
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Encode the image in the given format. PNG and JPG images require
+    /// the `images` feature.
+    pub fn encode(
+        &self,
+        format: ThumbnailFormat,
+    ) -> Result<Box<[u8]>, BinaryGcodeError> {
+        match format {
+            ThumbnailFormat::Qoi => Ok(qoi::encode(self)?.into_boxed_slice()),
+            #[cfg(feature = "images")]
+            ThumbnailFormat::Png => encode_png(self),
+            #[cfg(feature = "images")]
+            ThumbnailFormat::Jpg => encode_jpg(self),
+            #[cfg(not(feature = "images"))]
+            _ => Err(BinaryGcodeError::ImageError(format!(
+                "{:?} encoding requires the images feature",
+                format
+            ))),
+        }
+    }
+}
+
+/// Return the first source pixel and the normalised weights of the
+/// source pixels that make up each destination pixel.
+fn filter_weights(
+    src: usize,
+    dst: usize,
+) -> Vec<(usize, Vec<f32>)> {
+    let scale = src as f32 / dst as f32;
+    let support = scale.max(1.0);
+    (0..dst)
+        .map(|i| {
+            let centre = (i as f32 + 0.5) * scale - 0.5;
+            // Truncation only ever adds a sample with no weight.
+            let left = ((centre - support) as isize).max(0) as usize;
+            let right = ((centre + support) as usize + 1).min(src);
+            let mut weights: Vec<f32> = (left..right)
+                .map(|j| (1.0 - (j as f32 - centre).abs() / support).max(0.0))
+                .collect();
+            let total: f32 = weights.iter().sum();
+            if total > 0.0 {
+                weights.iter_mut().for_each(|w| *w /= total);
+            } else {
+                // Only possible when the centre is clamped to an edge.
+                let last = weights.len() - 1;
+                weights[last] = 1.0;
+            }
+            (left, weights)
+        })
+        .collect()
+}
+
+/// A change to the thumbnails of a binary gcode file.
+#[derive(Debug, Clone)]
+pub enum ThumbnailEdit {
+    /// Add a thumbnail after the existing ones.
+    Add(Thumbnail),
+    /// Replace any thumbnails with the same dimensions, or add it if
+    /// there are none.
+    Replace(Thumbnail),
+    /// Remove any thumbnails with the given dimensions.
+    Remove { width: u16, height: u16 },
+    /// Remove every thumbnail.
+    RemoveAll,
+}
+
+/// Apply a list of thumbnail edits to a binary gcode file in order. The
+/// file header and every other block are copied through untouched and
+/// the thumbnails are written where the first one was, or after the file
+/// and printer metadata if the file had none.
+pub fn edit_thumbnails(
+    binary: &[u8],
+    edits: &[ThumbnailEdit],
+) -> Result<Box<[u8]>, BinaryGcodeError> {
+    let (header, blocks) = deserialise_file(binary)?;
+
+    // Existing thumbnails keep their range so they can be copied as is.
+    let mut thumbs: Vec<(Option<Range<usize>>, Thumbnail)> = Vec::new();
+    for b in blocks
+        .iter()
+        .filter(|b| b.block.kind == BlockKind::Thumbnail)
+    {
+        let thumb = Thumbnail::from_block(&b.block)?;
+        thumbs.push((Some(b.range.clone()), thumb));
+    }
+    let same_size = |t: &Thumbnail, width: u16, height: u16| {
+        t.width == width && t.height == height
+    };
+    for edit in edits {
+        match edit {
+            ThumbnailEdit::Add(thumb) => thumbs.push((None, thumb.clone())),
+            ThumbnailEdit::Replace(thumb) => {
+                let (w, h) = (thumb.width, thumb.height);
+                match thumbs.iter().position(|(_, t)| same_size(t, w, h)) {
+                    Some(i) => {
+                        thumbs.retain(|(_, t)| !same_size(t, w, h));
+                        thumbs.insert(i, (None, thumb.clone()));
+                    }
+                    None => thumbs.push((None, thumb.clone())),
+                }
+            }
+            ThumbnailEdit::Remove { width, height } => {
+                thumbs.retain(|(_, t)| !same_size(t, *width, *height));
+            }
+            ThumbnailEdit::RemoveAll => thumbs.clear(),
+        }
+    }
+
+    let insert_at = blocks
+        .iter()
+        .position(|b| {
+            !matches!(
+                b.block.kind,
+                BlockKind::FileMetadata | BlockKind::PrinterMetadata
+            )
+        })
+        .map(|i| {
+            blocks[i..]
+                .iter()
+                .position(|b| b.block.kind == BlockKind::Thumbnail)
+                .map_or(i, |j| i + j)
+        })
+        .unwrap_or(blocks.len());
+
+    let header_end = blocks.first().map_or(binary.len(), |b| b.range.start);
+    let mut out = Vec::with_capacity(binary.len());
+    out.extend_from_slice(&binary[..header_end]);
+    for (i, b) in blocks.iter().enumerate() {
+        if i == insert_at {
+            write_thumbnails(&mut out, binary, &thumbs, header.checksum)?;
+        }
+        if b.block.kind != BlockKind::Thumbnail {
+            out.extend_from_slice(&binary[b.range.clone()]);
+        }
+    }
+    if insert_at == blocks.len() {
+        write_thumbnails(&mut out, binary, &thumbs, header.checksum)?;
+    }
+    Ok(out.into_boxed_slice())
+}
+
+fn write_thumbnails(
+    out: &mut Vec<u8>,
+    binary: &[u8],
+    thumbs: &[(Option<Range<usize>>, Thumbnail)],
+    checksum: Checksum,
+) -> Result<(), BinaryGcodeError> {
+    for (range, thumb) in thumbs {
+        match range {
+            Some(range) => out.extend_from_slice(&binary[range.clone()]),
+            None => {
+                thumb.validate()?;
+                let block = serialise_block(
+                    BlockKind::Thumbnail,
+                    CompressionAlgorithm::None,
+                    thumb.format.encoding(),
+                    checksum,
+                    &thumb.additional_parameters(),
+                    &thumb.data,
+                )?;
+                out.extend_from_slice(&block);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return all the thumbnails in a binary gcode file.
+pub fn thumbnails(binary: &[u8]) -> Result<Vec<Thumbnail>, BinaryGcodeError> {
+    let mut thumbnails = Vec::new();
+    let mut deserialiser = Deserialiser::default();
+    deserialiser.digest(binary);
+    loop {
+        match deserialiser.deserialise()? {
+            DeserialisedResult::FileHeader(_) => {}
+            DeserialisedResult::Block(b) => {
+                if b.kind == BlockKind::Thumbnail {
+                    thumbnails.push(Thumbnail::from_block(&b)?);
+                }
+            }
+            DeserialisedResult::MoreBytesRequired(_) => break,
+        }
+    }
+    Ok(thumbnails)
+}
+
+#[cfg(feature = "images")]
+fn decode_png(data: &[u8]) -> Result<RgbaImage, BinaryGcodeError> {
+    use alloc::string::ToString;
+    use png::{ColorType, Decoder, Transformations};
+
+    let mut decoder = Decoder::new(std::io::Cursor::new(data));
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| BinaryGcodeError::ImageError(e.to_string()))?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or(BinaryGcodeError::InvalidThumbnail("png_size"))?;
+    let mut buf = vec![0u8; size];
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|e| BinaryGcodeError::ImageError(e.to_string()))?;
+    let buf = &buf[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        ColorType::Rgba => buf.to_vec(),
+        ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        ColorType::Grayscale => {
+            buf.iter().flat_map(|l| [*l, *l, *l, 255]).collect()
+        }
+        // Expanded into rgb by the transformations.
+        ColorType::Indexed => {
+            return Err(BinaryGcodeError::InvalidThumbnail("png_indexed"))
+        }
+    };
+    Ok(RgbaImage {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+#[cfg(feature = "images")]
+fn encode_png(image: &RgbaImage) -> Result<Box<[u8]>, BinaryGcodeError> {
+    use alloc::string::ToString;
+    use png::{BitDepth, ColorType, Encoder};
+
+    let mut out = Vec::new();
+    let mut encoder = Encoder::new(&mut out, image.width, image.height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| BinaryGcodeError::ImageError(e.to_string()))?;
+    writer
+        .write_image_data(&image.pixels)
+        .map_err(|e| BinaryGcodeError::ImageError(e.to_string()))?;
+    writer
+        .finish()
+        .map_err(|e| BinaryGcodeError::ImageError(e.to_string()))?;
+    Ok(out.into_boxed_slice())
+}
+
+#[cfg(feature = "images")]
+fn encode_jpg(image: &RgbaImage) -> Result<Box<[u8]>, BinaryGcodeError> {
+    use alloc::string::ToString;
+    use jpeg_encoder::{ColorType, Encoder};
+
+    let width = u16::try_from(image.width)
+        .map_err(|_| BinaryGcodeError::InvalidThumbnail("jpg_dimensions"))?;
+    let height = u16::try_from(image.height)
+        .map_err(|_| BinaryGcodeError::InvalidThumbnail("jpg_dimensions"))?;
+    // Jpeg has no alpha channel so blend onto a white background.
+    let pixels: Vec<u8> = image
+        .pixels
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let a = p[3] as u16;
+            let c = |v: u8| ((v as u16 * a + 255 * (255 - a)) / 255) as u8;
+            [c(p[0]), c(p[1]), c(p[2])]
+        })
+        .collect();
+    let mut out = Vec::new();
+    Encoder::new(&mut out, 90)
+        .encode(&pixels, width, height, ColorType::Rgb)
+        .map_err(|e| BinaryGcodeError::ImageError(e.to_string()))?;
+    Ok(out.into_boxed_slice())
+}
+
+#[cfg(feature = "images")]
+fn decode_jpg(data: &[u8]) -> Result<RgbaImage, BinaryGcodeError> {
+    use alloc::string::ToString;
+    use jpeg_decoder::{Decoder, PixelFormat};
+
+    let mut decoder = Decoder::new(data);
+    let buf = decoder
+        .decode()
+        .map_err(|e| BinaryGcodeError::ImageError(e.to_string()))?;
+    let info = decoder
+        .info()
+        .ok_or(BinaryGcodeError::InvalidThumbnail("jpg_info"))?;
+
+    let pixels = match info.pixel_format {
+        PixelFormat::RGB24 => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        PixelFormat::L8 => buf.iter().flat_map(|l| [*l, *l, *l, 255]).collect(),
+        // Big endian 16 bit luminance.
+        PixelFormat::L16 => buf
+            .chunks_exact(2)
+            .flat_map(|l| [l[0], l[0], l[0], 255])
+            .collect(),
+        PixelFormat::CMYK32 => buf
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let k = p[3] as u16;
+                let c = |v: u8| (v as u16 * k / 255) as u8;
+                [c(p[0]), c(p[1]), c(p[2]), 255]
+            })
+            .collect(),
+    };
+    Ok(RgbaImage {
+        width: info.width as u32,
+        height: info.height as u32,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        edit_thumbnails, thumbnails, ImageInfo, RgbaImage, Thumbnail,
+        ThumbnailEdit, ThumbnailFormat,
+    };
+    use crate::components::common::BlockKind;
+    use crate::components::deserialiser::deserialise_file;
+    use crate::BinaryGcodeError;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn corrupt_qoi_is_rejected() {
+        use crate::components::common::{crc32, Checksum};
+        use crate::components::deserialiser::BlockLayout;
+
+        let binary =
+            include_bytes!("../../test_files/mini_cube_ps2.8.1.bgcode");
+        let (_, blocks) = deserialise_file(binary).unwrap();
+        let thumb = blocks
+            .iter()
+            .find(|b| b.block.kind == BlockKind::Thumbnail)
+            .unwrap();
+        assert!(Thumbnail::from_block_checked(&thumb.block).is_ok());
+
+        // Replace the pixels with ops that run out of data, keeping the
+        // header and end marker, then fix up the checksum.
+        let layout =
+            BlockLayout::read(binary, thumb.range.start, Checksum::Crc32)
+                .unwrap();
+        let mut corrupt = binary.to_vec();
+        corrupt[layout.data.start + 14..layout.data.end - 8].fill(0xff);
+        let crc = crc32(&corrupt[layout.range.start..layout.data.end]);
+        corrupt[layout.data.end..layout.range.end]
+            .copy_from_slice(&crc.to_le_bytes());
+
+        let (_, blocks) = deserialise_file(&corrupt).unwrap();
+        let block = &blocks
+            .iter()
+            .find(|b| b.range == thumb.range)
+            .unwrap()
+            .block;
+        assert!(Thumbnail::from_block(block).unwrap().validate().is_ok());
+        assert!(matches!(
+            Thumbnail::from_block_checked(block),
+            Err(BinaryGcodeError::InvalidThumbnail("qoi_truncated"))
+        ));
+    }
+
+    #[test]
+    fn thumbnails_from_file() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let thumbs = thumbnails(binary).unwrap();
+        assert_eq!(thumbs.len(), 2);
+        assert_eq!(thumbs[0].format, ThumbnailFormat::Png);
+        assert_eq!((thumbs[0].width, thumbs[0].height), (16, 16));
+        assert_eq!((thumbs[1].width, thumbs[1].height), (220, 124));
+        assert!(thumbs[1].data.starts_with(b"\x89PNG"));
+    }
+
+    #[cfg(feature = "images")]
+    #[test]
+    fn decode_png_thumbnail() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let thumbs = thumbnails(binary).unwrap();
+        let image = thumbs[1].decode().unwrap();
+        assert_eq!((image.width, image.height), (220, 124));
+        assert_eq!(image.pixels.len(), 220 * 124 * 4);
+
+        // Transcode to QOI and back to PNG without losing any pixels.
+        let qoi = image.encode(ThumbnailFormat::Qoi).unwrap();
+        let png = RgbaImage::decode(ThumbnailFormat::Qoi, &qoi)
+            .unwrap()
+            .encode(ThumbnailFormat::Png)
+            .unwrap();
+        let decoded = RgbaImage::decode(ThumbnailFormat::Png, &png).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn thumbnail_mismatch() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let mut thumb = thumbnails(binary).unwrap().remove(1);
+        let info = ImageInfo::from_image(&thumb.data).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ThumbnailFormat::Png, 220, 124)
+        );
+
+        thumb.width = 200;
+        assert!(matches!(
+            thumb.validate(),
+            Err(BinaryGcodeError::ThumbnailMismatch { declared, actual })
+                if declared.width == 200 && actual.width == 220
+        ));
+        thumb.width = 220;
+        thumb.format = ThumbnailFormat::Qoi;
+        assert!(matches!(
+            edit_thumbnails(binary, &[ThumbnailEdit::Add(thumb)]),
+            Err(BinaryGcodeError::ThumbnailMismatch { .. })
+        ));
+        assert!(ImageInfo::from_image(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn resize_keeps_colours() {
+        // Opaque red on the left, transparent blue on the right.
+        let pixels = (0..40 * 20)
+            .flat_map(|i| match i % 40 < 20 {
+                true => [255, 0, 0, 255],
+                false => [0, 0, 255, 0],
+            })
+            .collect();
+        let image = RgbaImage {
+            width: 40,
+            height: 20,
+            pixels,
+        };
+        for (w, h) in [(10, 5), (16, 16), (100, 50)] {
+            let resized = image.resize(w, h).unwrap();
+            assert_eq!(resized.pixels.len(), (w * h * 4) as usize);
+            // Transparent pixels must not darken the red.
+            for px in resized.pixels.chunks_exact(4).filter(|p| p[3] > 0) {
+                assert_eq!(px[..3], [255, 0, 0]);
+            }
+            assert_eq!(resized.pixels[..4], [255, 0, 0, 255]);
+        }
+        assert!(image.resize(0, 10).is_err());
+    }
+
+    #[test]
+    fn edit_file_thumbnails() {
+        let binary =
+            include_bytes!("../../test_files/mini_cube_ps2.8.1.bgcode");
+        let thumbs = thumbnails(binary).unwrap();
+        let image = thumbs[0].decode().unwrap();
+        let small = Thumbnail::from_image(&image, ThumbnailFormat::Qoi, 24, 24)
+            .unwrap();
+        let replacement = Thumbnail::from_image(
+            &image,
+            ThumbnailFormat::Qoi,
+            thumbs[1].width,
+            thumbs[1].height,
+        )
+        .unwrap();
+
+        let edited = edit_thumbnails(
+            binary,
+            &[
+                ThumbnailEdit::Remove {
+                    width: thumbs[0].width,
+                    height: thumbs[0].height,
+                },
+                ThumbnailEdit::Replace(replacement.clone()),
+                ThumbnailEdit::Add(small),
+            ],
+        )
+        .unwrap();
+        let edited_thumbs = thumbnails(&edited).unwrap();
+        let sizes: Vec<(u16, u16)> =
+            edited_thumbs.iter().map(|t| (t.width, t.height)).collect();
+        let mut expected: Vec<(u16, u16)> =
+            thumbs[1..].iter().map(|t| (t.width, t.height)).collect();
+        expected.push((24, 24));
+        assert_eq!(sizes, expected);
+        assert_eq!(edited_thumbs[0].data, replacement.data);
+
+        // Every other block is copied through untouched.
+        let others = |binary: &[u8]| -> Vec<Vec<u8>> {
+            let (_, blocks) = deserialise_file(binary).unwrap();
+            blocks
+                .iter()
+                .filter(|b| b.block.kind != BlockKind::Thumbnail)
+                .map(|b| binary[b.range.clone()].to_vec())
+                .collect()
+        };
+        assert_eq!(others(binary), others(&edited));
+
+        // Removing everything leaves no thumbnails behind.
+        let stripped =
+            edit_thumbnails(binary, &[ThumbnailEdit::RemoveAll]).unwrap();
+        assert!(thumbnails(&stripped).unwrap().is_empty());
+        assert_eq!(others(binary), others(&stripped));
+    }
+
+    #[cfg(feature = "images")]
+    #[test]
+    fn encode_jpg_thumbnail() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let image = thumbnails(binary).unwrap()[1].decode().unwrap();
+        let thumb =
+            Thumbnail::from_image(&image, ThumbnailFormat::Jpg, 110, 62)
+                .unwrap();
+        let info = ImageInfo::from_image(&thumb.data).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ThumbnailFormat::Jpg, 110, 62)
+        );
+        let decoded = thumb.decode().unwrap();
+        assert_eq!((decoded.width, decoded.height), (110, 62));
+    }
+}
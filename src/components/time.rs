@@ -0,0 +1,651 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+use crate::components::common::{BinaryGcodeError, BlockKind};
+use crate::components::deserialiser::deserialise_file;
+use crate::components::gcode::{parse_gcode, Arc, Command, GcodeLine, Move};
+use crate::components::math::sqrt;
+use crate::components::stats::{arc_length, move_target};
+
+/// The speed changes below which a junction is treated as a straight line
+/// when using junction deviation.
+const STRAIGHT_JUNCTION: f64 = 0.999_999;
+
+/// The motion limits of a printer. Any `M201`, `M203`, `M204` and `M205`
+/// commands in the gcode override these as they are reached.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MachineLimits {
+    /// The maximum acceleration of the x, y, z and e axes in mm/s².
+    pub max_acceleration: [f64; 4],
+    /// The maximum feedrate of the x, y, z and e axes in mm/s.
+    pub max_feedrate: [f64; 4],
+    /// The acceleration of extruding moves in mm/s².
+    pub acceleration: f64,
+    /// The acceleration of extruder only moves in mm/s².
+    pub retract_acceleration: f64,
+    /// The acceleration of moves that do not extrude in mm/s².
+    pub travel_acceleration: f64,
+    /// The largest instant speed change of each axis in mm/s.
+    pub jerk: [f64; 4],
+    /// The junction deviation in mm. When set it is used in place of the
+    /// jerk of the x, y and z axes.
+    pub junction_deviation: Option<f64>,
+    /// The minimum feedrate of extruding moves in mm/s.
+    pub min_feedrate: f64,
+    /// The minimum feedrate of travel moves in mm/s.
+    pub min_travel_feedrate: f64,
+}
+
+impl Default for MachineLimits {
+    /// The Marlin defaults.
+    fn default() -> Self {
+        Self {
+            max_acceleration: [3000.0, 3000.0, 100.0, 10000.0],
+            max_feedrate: [300.0, 300.0, 5.0, 25.0],
+            acceleration: 3000.0,
+            retract_acceleration: 3000.0,
+            travel_acceleration: 3000.0,
+            jerk: [10.0, 10.0, 0.3, 5.0],
+            junction_deviation: None,
+            min_feedrate: 0.0,
+            min_travel_feedrate: 0.0,
+        }
+    }
+}
+
+/// The estimated time of a print.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TimeEstimate {
+    /// The total time in seconds.
+    pub total: f64,
+    /// The time in seconds at which each line of the gcode is finished.
+    pub line_times: Vec<f64>,
+}
+
+impl TimeEstimate {
+    /// Estimate the print time of some ascii gcode.
+    pub fn from_gcode(
+        gcode: &[u8],
+        limits: MachineLimits,
+    ) -> Self {
+        let mut estimator = TimeEstimator::new(limits);
+        for line in parse_gcode(gcode) {
+            estimator.line(&line);
+        }
+        estimator.finish()
+    }
+
+    /// Estimate the print time of the gcode blocks of a binary gcode file.
+    /// The line times count the lines of every gcode block in order.
+    pub fn from_binary(
+        binary: &[u8],
+        limits: MachineLimits,
+    ) -> Result<Self, BinaryGcodeError> {
+        let (_, blocks) = deserialise_file(binary)?;
+        let mut estimator = TimeEstimator::new(limits);
+        for b in blocks.iter().filter(|b| b.block.kind == BlockKind::GCode) {
+            for line in parse_gcode(&b.block.gcode()?) {
+                estimator.line(&line);
+            }
+        }
+        Ok(estimator.finish())
+    }
+}
+
+/// A move or pause that the planner has queued.
+#[derive(Debug, Clone, Copy)]
+struct PlannedMove {
+    line: usize,
+    distance: f64,
+    /// The speed in mm/s the move would cruise at.
+    nominal: f64,
+    acceleration: f64,
+    /// The fastest the move can be entered given the junction with the
+    /// previous move.
+    max_entry: f64,
+    entry: f64,
+    /// The time of a pause which stops the printer.
+    dwell: f64,
+}
+
+impl PlannedMove {
+    /// Return the time of a trapezoid from the entry speed up to the
+    /// nominal speed and down to the exit speed.
+    fn duration(
+        &self,
+        exit: f64,
+    ) -> f64 {
+        if self.distance == 0.0 {
+            return self.dwell;
+        }
+        let (v0, v1, a) = (self.entry, exit, self.acceleration);
+        let accelerate = (self.nominal * self.nominal - v0 * v0) / (2.0 * a);
+        let decelerate = (self.nominal * self.nominal - v1 * v1) / (2.0 * a);
+        if accelerate + decelerate <= self.distance {
+            let cruise = self.distance - accelerate - decelerate;
+            (self.nominal - v0) / a
+                + (self.nominal - v1) / a
+                + cruise / self.nominal
+        } else {
+            // The nominal speed is never reached.
+            let peak =
+                sqrt((2.0 * a * self.distance + v0 * v0 + v1 * v1) / 2.0)
+                    .max(v0)
+                    .max(v1);
+            (peak - v0) / a + (peak - v1) / a
+        }
+    }
+}
+
+/// Simulates the motion planner of the firmware over the gcode.
+struct TimeEstimator {
+    limits: MachineLimits,
+    position: [f64; 4],
+    absolute: bool,
+    absolute_e: bool,
+    /// The last feedrate in mm/s.
+    feedrate: f64,
+    /// The feedrate override as a factor.
+    feedrate_factor: f64,
+    moves: Vec<PlannedMove>,
+    lines: usize,
+    /// The axis speeds, direction, nominal and safe speed of the last
+    /// move, or `None` if the printer is stopped.
+    previous: Option<([f64; 4], [f64; 3], f64, f64)>,
+}
+
+impl TimeEstimator {
+    fn new(limits: MachineLimits) -> Self {
+        Self {
+            limits,
+            position: [0.0; 4],
+            absolute: true,
+            absolute_e: true,
+            feedrate: 25.0,
+            feedrate_factor: 1.0,
+            moves: Vec::new(),
+            lines: 0,
+            previous: None,
+        }
+    }
+
+    fn line(
+        &mut self,
+        line: &GcodeLine,
+    ) {
+        let limits = &mut self.limits;
+        // Sets the values that are given.
+        let set = |target: &mut f64, value: Option<f32>| {
+            if let Some(v) = value {
+                *target = v as f64;
+            }
+        };
+        match &line.command {
+            Some(Command::G0(m) | Command::G1(m)) => self.linear(m, None),
+            Some(Command::G2(a)) => self.arc(a, true),
+            Some(Command::G3(a)) => self.arc(a, false),
+            Some(Command::G4 { p, s }) => {
+                let seconds = s.map(|s| s as f64).unwrap_or_default()
+                    + p.map(|p| p as f64 / 1000.0).unwrap_or_default();
+                self.pause(seconds);
+            }
+            Some(Command::G90) => {
+                self.absolute = true;
+                self.absolute_e = true;
+            }
+            Some(Command::G91) => {
+                self.absolute = false;
+                self.absolute_e = false;
+            }
+            Some(Command::M82) => self.absolute_e = true,
+            Some(Command::M83) => self.absolute_e = false,
+            Some(Command::G92(axes)) => {
+                let values = [axes.x, axes.y, axes.z, axes.e];
+                for (axis, value) in values.into_iter().enumerate() {
+                    set(&mut self.position[axis], value);
+                }
+            }
+            Some(Command::M201(axes)) => {
+                let values = [axes.x, axes.y, axes.z, axes.e];
+                for (axis, value) in values.into_iter().enumerate() {
+                    set(&mut limits.max_acceleration[axis], value);
+                }
+            }
+            Some(Command::M203(axes)) => {
+                let values = [axes.x, axes.y, axes.z, axes.e];
+                for (axis, value) in values.into_iter().enumerate() {
+                    set(&mut limits.max_feedrate[axis], value);
+                }
+            }
+            Some(Command::M204 { p, r, t, s }) => {
+                // S sets both the print and travel accelerations.
+                set(&mut limits.acceleration, *s);
+                set(&mut limits.travel_acceleration, *s);
+                set(&mut limits.acceleration, *p);
+                set(&mut limits.retract_acceleration, *r);
+                set(&mut limits.travel_acceleration, *t);
+            }
+            Some(Command::M205 {
+                x,
+                y,
+                z,
+                e,
+                s,
+                t,
+                j,
+            }) => {
+                let values = [*x, *y, *z, *e];
+                for (axis, value) in values.into_iter().enumerate() {
+                    set(&mut limits.jerk[axis], value);
+                }
+                set(&mut limits.min_feedrate, *s);
+                set(&mut limits.min_travel_feedrate, *t);
+                if let Some(j) = j {
+                    limits.junction_deviation = Some(*j as f64);
+                }
+            }
+            Some(Command::M220 { s: Some(s) }) => {
+                self.feedrate_factor = *s as f64 / 100.0;
+            }
+            _ => {}
+        }
+        self.lines += 1;
+    }
+
+    /// Return where a move ends following the positioning modes.
+    fn target(
+        &self,
+        m: &Move,
+    ) -> [f64; 4] {
+        move_target(&self.position, m, self.absolute, self.absolute_e)
+    }
+
+    fn arc(
+        &mut self,
+        a: &Arc,
+        clockwise: bool,
+    ) {
+        let m = Move {
+            x: a.x,
+            y: a.y,
+            z: a.z,
+            e: a.e,
+            f: a.f,
+        };
+        let to = self.target(&m);
+        let length = arc_length(&self.position, &to, a, clockwise);
+        self.linear(&m, Some(length));
+    }
+
+    /// Queue a move. Arcs are planned as a single move of their length
+    /// heading along their chord.
+    fn linear(
+        &mut self,
+        m: &Move,
+        length: Option<f64>,
+    ) {
+        if let Some(f) = m.f {
+            self.feedrate = f as f64 / 60.0;
+        }
+        let to = self.target(m);
+        let delta: [f64; 4] =
+            core::array::from_fn(|a| to[a] - self.position[a]);
+        self.position = to;
+
+        let xyz = sqrt(
+            delta[0] * delta[0] + delta[1] * delta[1] + delta[2] * delta[2],
+        );
+        let distance = match (xyz > 0.0, length) {
+            (true, Some(length)) => length,
+            (true, None) => xyz,
+            (false, _) => delta[3].abs(),
+        };
+        if distance < 1e-6 {
+            return;
+        }
+        let limits = &self.limits;
+        let extruding = delta[3] != 0.0;
+
+        let mut nominal = self.feedrate * self.feedrate_factor;
+        nominal = nominal.max(match extruding {
+            true => limits.min_feedrate,
+            false => limits.min_travel_feedrate,
+        });
+        let mut acceleration = match (xyz > 0.0, extruding) {
+            (false, _) => limits.retract_acceleration,
+            (true, true) => limits.acceleration,
+            (true, false) => limits.travel_acceleration,
+        };
+        // Slow down for any axis that would go over its limits.
+        for (axis, d) in delta.iter().enumerate() {
+            let share = d.abs() / distance;
+            if share > 0.0 {
+                nominal = nominal.min(limits.max_feedrate[axis] / share);
+                acceleration =
+                    acceleration.min(limits.max_acceleration[axis] / share);
+            }
+        }
+        let speeds: [f64; 4] =
+            core::array::from_fn(|a| delta[a] / distance * nominal);
+        let direction = match xyz > 0.0 {
+            true => [delta[0] / xyz, delta[1] / xyz, delta[2] / xyz],
+            false => [0.0; 3],
+        };
+
+        // The speed the move can start at or stop from with no junction.
+        let mut safe = nominal;
+        for (speed, jerk) in speeds.iter().zip(limits.jerk) {
+            let speed = speed.abs();
+            if speed > jerk {
+                safe = safe.min(nominal * jerk / speed);
+            }
+        }
+
+        let max_entry = match self.previous {
+            None => safe,
+            Some((
+                previous_speeds,
+                previous_direction,
+                previous_nominal,
+                previous_safe,
+            )) => match limits.junction_deviation {
+                Some(deviation) if xyz > 0.0 => junction_deviation_speed(
+                    deviation,
+                    acceleration,
+                    &previous_direction,
+                    &direction,
+                )
+                .min(nominal)
+                .min(previous_nominal),
+                _ => jerk_speed(
+                    &limits.jerk,
+                    &previous_speeds,
+                    previous_nominal,
+                    previous_safe,
+                    &speeds,
+                    nominal,
+                    safe,
+                ),
+            },
+        };
+
+        self.previous = Some((speeds, direction, nominal, safe));
+        self.moves.push(PlannedMove {
+            line: self.lines,
+            distance,
+            nominal,
+            acceleration,
+            max_entry,
+            entry: 0.0,
+            dwell: 0.0,
+        });
+    }
+
+    /// Stop the printer and wait.
+    fn pause(
+        &mut self,
+        seconds: f64,
+    ) {
+        self.previous = None;
+        self.moves.push(PlannedMove {
+            line: self.lines,
+            distance: 0.0,
+            nominal: 0.0,
+            acceleration: 0.0,
+            max_entry: 0.0,
+            entry: 0.0,
+            dwell: seconds,
+        });
+    }
+
+    /// Plan the entry speed of every move and add up their times.
+    fn finish(mut self) -> TimeEstimate {
+        let moves = &mut self.moves;
+        // Every move must be able to slow down for the next one.
+        let mut exit = 0.0;
+        for m in moves.iter_mut().rev() {
+            m.entry = m
+                .max_entry
+                .min(sqrt(exit * exit + 2.0 * m.acceleration * m.distance));
+            exit = m.entry;
+        }
+        // And be reachable from the previous one. Starting from a stop is
+        // already limited to the safe speed by the junction.
+        let mut reachable = f64::MAX;
+        for m in moves.iter_mut() {
+            if m.distance == 0.0 {
+                reachable = f64::MAX;
+                continue;
+            }
+            m.entry = m.entry.min(reachable);
+            reachable =
+                sqrt(m.entry * m.entry + 2.0 * m.acceleration * m.distance);
+        }
+
+        let mut line_times = Vec::with_capacity(self.lines);
+        let mut time = 0.0;
+        for (i, m) in moves.iter().enumerate() {
+            let exit = moves.get(i + 1).map_or(0.0, |next| next.entry);
+            line_times.resize(m.line, time);
+            time += m.duration(exit.min(m.nominal));
+        }
+        line_times.resize(self.lines, time);
+        TimeEstimate {
+            total: time,
+            line_times,
+        }
+    }
+}
+
+/// Return the fastest speed through a junction that keeps the speed
+/// change of every axis within its jerk, following the classic Marlin
+/// planner.
+fn jerk_speed(
+    jerk: &[f64; 4],
+    previous_speeds: &[f64; 4],
+    previous_nominal: f64,
+    previous_safe: f64,
+    speeds: &[f64; 4],
+    nominal: f64,
+    safe: f64,
+) -> f64 {
+    if previous_nominal < 1e-4 {
+        return safe;
+    }
+    // Scale the faster move down to the speed of the slower one.
+    let previous_larger = previous_nominal > nominal;
+    let factor = match previous_larger {
+        true => nominal / previous_nominal,
+        false => previous_nominal / nominal,
+    };
+    let mut junction = match previous_larger {
+        true => nominal,
+        false => previous_nominal,
+    };
+    let mut v_factor = 1.0;
+    let mut limited = false;
+    for axis in 0..4 {
+        let mut exit = previous_speeds[axis];
+        let mut entry = speeds[axis];
+        if previous_larger {
+            exit *= factor;
+        } else {
+            entry *= factor;
+        }
+        if limited {
+            exit *= v_factor;
+            entry *= v_factor;
+        }
+        // Reversing direction needs the full change but continuing in
+        // the same direction only the difference.
+        let change = if exit > entry {
+            if entry > 0.0 || exit < 0.0 {
+                exit - entry
+            } else {
+                exit.max(-entry)
+            }
+        } else if entry < 0.0 || exit > 0.0 {
+            entry - exit
+        } else {
+            (-exit).max(entry)
+        };
+        if change > jerk[axis] {
+            v_factor *= jerk[axis] / change;
+            limited = true;
+        }
+    }
+    if limited {
+        junction *= v_factor;
+    }
+    // Both moves can stop and start here safely anyway.
+    let threshold = junction * 0.99;
+    if previous_safe > threshold && safe > threshold {
+        junction = safe;
+    }
+    junction
+}
+
+/// Return the fastest speed through a junction that keeps the path within
+/// the junction deviation of the corner.
+fn junction_deviation_speed(
+    deviation: f64,
+    acceleration: f64,
+    previous: &[f64; 3],
+    current: &[f64; 3],
+) -> f64 {
+    let cos_theta = -(previous[0] * current[0]
+        + previous[1] * current[1]
+        + previous[2] * current[2]);
+    if cos_theta > STRAIGHT_JUNCTION {
+        // A full reversal.
+        return 0.0;
+    }
+    if cos_theta < -STRAIGHT_JUNCTION {
+        return f64::MAX;
+    }
+    let sin_theta_d2 = sqrt(0.5 * (1.0 - cos_theta));
+    sqrt(acceleration * deviation * sin_theta_d2 / (1.0 - sin_theta_d2))
+}
+
+/// Replace the `M73` progress lines of some ascii gcode with ones computed
+/// by the time estimator. A line setting the percentage done and the whole
+/// minutes remaining is written whenever either changes, starting with
+/// `M73 P0` and ending with `M73 P100 R0`. Silent mode `M73 Q.. S..`
+/// lines are kept as they are, as only one set of limits is simulated.
+pub fn regenerate_progress(
+    gcode: &[u8],
+    limits: MachineLimits,
+) -> Vec<u8> {
+    let estimate = TimeEstimate::from_gcode(gcode, limits);
+    let total = estimate.total;
+    let mut out = Vec::with_capacity(gcode.len() + 4096);
+    let mut last = None;
+    let mut progress = |out: &mut Vec<u8>, time: f64| {
+        let percent = match total > 0.0 {
+            true => ((time / total * 100.0) as u32).min(99),
+            false => 0,
+        };
+        let remaining = ((total - time) / 60.0) as u32;
+        if last != Some((percent, remaining)) {
+            last = Some((percent, remaining));
+            let mut line = String::new();
+            // Writing to a string can not fail.
+            let _ = writeln!(line, "M73 P{} R{}", percent, remaining);
+            out.extend_from_slice(line.as_bytes());
+        }
+    };
+
+    let mut start = 0.0;
+    for (i, line) in parse_gcode(gcode).enumerate() {
+        match &line.command {
+            Some(Command::M73 { p, r }) if p.is_some() || r.is_some() => {
+                continue;
+            }
+            _ => {}
+        }
+        progress(&mut out, start);
+        out.extend_from_slice(line.raw);
+        if !line.raw.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+        start = estimate.line_times[i];
+    }
+    out.extend_from_slice(b"M73 P100 R0\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{regenerate_progress, MachineLimits, TimeEstimate};
+    use crate::components::gcode::{parse_gcode, Command};
+    use crate::components::math::sqrt;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn estimate_matches_slicer() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let estimate =
+            TimeEstimate::from_binary(binary, MachineLimits::default())
+                .unwrap();
+        // The slicer estimates 32m 6s.
+        let slicer = 32.0 * 60.0 + 6.0;
+        assert!(
+            (estimate.total - slicer).abs() / slicer < 0.01,
+            "{}",
+            estimate.total
+        );
+        assert!(estimate.line_times.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn trapezoid_times() {
+        // 100 mm at 50 mm/s with 1000 mm/s² spends 0.05 s accelerating
+        // over 1.25 mm at each end.
+        let limits = MachineLimits {
+            travel_acceleration: 1000.0,
+            jerk: [0.0; 4],
+            ..Default::default()
+        };
+        let gcode = b"G1 X100 F3000\nG4 S1\nG1 X101\n";
+        let estimate = TimeEstimate::from_gcode(gcode, limits);
+        let first = 0.05 + 0.05 + 97.5 / 50.0;
+        assert!((estimate.line_times[0] - first).abs() < 1e-9);
+        assert!((estimate.line_times[1] - first - 1.0).abs() < 1e-9);
+        // A 1 mm move never reaches 50 mm/s.
+        let short = 2.0 * sqrt(1.0 / 1000.0);
+        assert!((estimate.total - first - 1.0 - short).abs() < 1e-9);
+    }
+
+    #[test]
+    fn regenerated_progress() {
+        let gcode = include_bytes!("../../test_files/mini_cube_b.gcode");
+        let start = gcode.windows(6).position(|w| w == b"M73 P0").unwrap();
+        let gcode = &gcode[start..];
+        let regenerated = regenerate_progress(gcode, MachineLimits::default());
+        assert!(regenerated.starts_with(b"M73 P0 R"));
+        assert!(regenerated.ends_with(b"M73 P100 R0\n"));
+        let progress: Vec<_> = parse_gcode(&regenerated)
+            .filter_map(|l| match l.command {
+                Some(Command::M73 { p, r }) => Some((p.unwrap(), r.unwrap())),
+                _ => None,
+            })
+            .collect();
+        assert!(progress
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].1 >= w[1].1));
+        assert!(progress.len() > 100);
+    }
+
+    #[test]
+    fn regenerated_progress_keeps_silent_mode() {
+        let gcode = b"M73 P0 R5\nM73 Q0 S6\nG1 X100 F600\nM73 Q50 S3\nG1 X0\nM73 P100 R0\nM73 Q100 S0\n";
+        let regenerated = regenerate_progress(gcode, MachineLimits::default());
+        let regenerated = core::str::from_utf8(&regenerated).unwrap();
+        let silent: Vec<_> = regenerated
+            .lines()
+            .filter(|l| l.starts_with("M73 Q"))
+            .collect();
+        assert_eq!(silent, ["M73 Q0 S6", "M73 Q50 S3", "M73 Q100 S0"]);
+        assert!(!regenerated.contains("M73 P0 R5"));
+    }
+}
@@ -0,0 +1,236 @@
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::components::common::{
+    BinaryGcodeError, BlockKind, Checksum, CompressionAlgorithm, Encoding,
+};
+use crate::components::convert::gcode_chunks;
+use crate::components::deserialiser::{deserialise_file, unpack_gcode};
+use crate::components::layers::{embed_layer_index, LayerIndex};
+use crate::components::serialiser::{
+    pack_gcode, serialise_block_with_level, CompressionLevel,
+};
+
+/// The compressions and gcode encoding a file is transcoded to. Those left
+/// as `None` keep whatever each block already has.
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeOptions {
+    /// The compression effort used for the blocks that are rewritten.
+    pub level: CompressionLevel,
+    /// The compression of the file, printer and print metadata blocks.
+    pub metadata_compression: Option<CompressionAlgorithm>,
+    pub thumbnail_compression: Option<CompressionAlgorithm>,
+    pub gcode_compression: Option<CompressionAlgorithm>,
+    pub slicer_metadata_compression: Option<CompressionAlgorithm>,
+    /// The encoding of the gcode blocks. `Meatpack` drops the comments and
+    /// `MeatpackWithComments` keeps them.
+    pub gcode_encoding: Option<Encoding>,
+}
+
+impl TranscodeOptions {
+    /// Return the compression set for a kind of block.
+    fn compression(
+        &self,
+        kind: BlockKind,
+    ) -> Option<CompressionAlgorithm> {
+        match kind {
+            BlockKind::FileMetadata
+            | BlockKind::PrinterMetadata
+            | BlockKind::PrintMetadata => self.metadata_compression,
+            BlockKind::Thumbnail => self.thumbnail_compression,
+            BlockKind::GCode => self.gcode_compression,
+            BlockKind::SlicerMetadata => self.slicer_metadata_compression,
+        }
+    }
+}
+
+/// Pack gcode into blocks with a compression and encoding, split on lines
+/// so each fits the block size limit.
+fn write_gcode(
+    out: &mut Vec<u8>,
+    (compression, encoding, gcode): (CompressionAlgorithm, Encoding, Vec<u8>),
+    options: &TranscodeOptions,
+    checksum: Checksum,
+) -> Result<(), BinaryGcodeError> {
+    for chunk in gcode_chunks(&gcode) {
+        let data = pack_gcode(encoding, chunk)?;
+        out.extend(serialise_block_with_level(
+            BlockKind::GCode,
+            compression,
+            options.level,
+            encoding,
+            checksum,
+            &[],
+            &data,
+        )?);
+    }
+    Ok(())
+}
+
+/// Rewrite the blocks of a binary gcode file with other compressions and
+/// gcode encoding without going through ascii gcode. Only the payloads of
+/// the blocks that change are decoded and encoded again, so metadata and
+/// thumbnails keep their exact contents and blocks that stay the same are
+/// copied byte for byte. Gcode changing encoding is unpacked as a whole and
+/// split into blocks again, as its size changes with the encoding. A stored
+/// layer index is rebuilt as the blocks it points at move.
+pub fn transcode(
+    binary: &[u8],
+    options: &TranscodeOptions,
+) -> Result<Box<[u8]>, BinaryGcodeError> {
+    match options.gcode_encoding {
+        None
+        | Some(
+            Encoding::Ascii
+            | Encoding::Meatpack
+            | Encoding::MeatpackWithComments,
+        ) => {}
+        Some(_) => {
+            return Err(BinaryGcodeError::SerialiseError("gcode_encoding"))
+        }
+    }
+    let (header, blocks) = deserialise_file(binary)?;
+    let header_end = blocks.first().map_or(binary.len(), |b| b.range.start);
+    let mut out = Vec::with_capacity(binary.len());
+    out.extend_from_slice(&binary[..header_end]);
+    let mut changed = false;
+    // The gcode changing encoding so far, with the compression and
+    // encoding its blocks are written with.
+    let mut pending: Option<(CompressionAlgorithm, Encoding, Vec<u8>)> = None;
+    for b in &blocks {
+        let block = &b.block;
+        let compression =
+            options.compression(block.kind).unwrap_or(block.compression);
+        let encoding = match block.kind {
+            BlockKind::GCode => {
+                options.gcode_encoding.unwrap_or(block.encoding)
+            }
+            _ => block.encoding,
+        };
+        if block.kind == BlockKind::GCode && encoding != block.encoding {
+            changed = true;
+            let data = block.decompress()?;
+            let (.., gcode) = pending
+                .get_or_insert_with(|| (compression, encoding, Vec::new()));
+            unpack_gcode(block.encoding, &data, gcode)?;
+            continue;
+        }
+        if let Some(gcode) = pending.take() {
+            write_gcode(&mut out, gcode, options, header.checksum)?;
+        }
+        if compression == block.compression && encoding == block.encoding {
+            out.extend_from_slice(&binary[b.range.clone()]);
+            continue;
+        }
+        changed = true;
+        let data = block.decompress()?;
+        // The encoding is written by the serialiser so only the parameters
+        // after it are passed on.
+        out.extend(serialise_block_with_level(
+            block.kind,
+            compression,
+            options.level,
+            encoding,
+            header.checksum,
+            &block.parameters[2..],
+            &data,
+        )?);
+    }
+    if let Some(gcode) = pending.take() {
+        write_gcode(&mut out, gcode, options, header.checksum)?;
+    }
+
+    match changed && LayerIndex::from_metadata(binary)?.is_some() {
+        true => embed_layer_index(&out),
+        false => Ok(out.into_boxed_slice()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{transcode, TranscodeOptions};
+    use crate::binary_to_ascii;
+    use crate::components::common::{
+        BlockKind, CompressionAlgorithm, Encoding,
+    };
+    use crate::components::deserialiser::{deserialise_file, FramedBlock};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn transcode_round_trip() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let options = TranscodeOptions {
+            metadata_compression: Some(CompressionAlgorithm::Deflate),
+            gcode_compression: Some(CompressionAlgorithm::Deflate),
+            gcode_encoding: Some(Encoding::Ascii),
+            ..Default::default()
+        };
+        let unpacked = transcode(binary, &options).unwrap();
+        assert_eq!(
+            binary_to_ascii(&unpacked, false).unwrap(),
+            binary_to_ascii(binary, false).unwrap()
+        );
+
+        // Thumbnails are copied and the other blocks hold the same data.
+        let (_, before) = deserialise_file(binary).unwrap();
+        let (_, after) = deserialise_file(&unpacked).unwrap();
+        let is_gcode = |b: &&FramedBlock| b.block.kind == BlockKind::GCode;
+        let (before_rest, after_rest): (Vec<_>, Vec<_>) = (
+            before.iter().filter(|b| !is_gcode(b)).collect(),
+            after.iter().filter(|b| !is_gcode(b)).collect(),
+        );
+        assert_eq!(before_rest.len(), after_rest.len());
+        for (a, b) in before_rest.iter().zip(&after_rest) {
+            assert_eq!(a.block.kind, b.block.kind);
+            let data = |b: &FramedBlock| b.block.decompress().unwrap();
+            match a.block.kind {
+                BlockKind::Thumbnail => {
+                    assert_eq!(
+                        binary[a.range.clone()],
+                        unpacked[b.range.clone()]
+                    )
+                }
+                _ => assert_eq!(data(a), data(b)),
+            }
+        }
+        // The unpacked gcode is split again rather than growing past the
+        // size of a block.
+        for b in after.iter().filter(is_gcode) {
+            assert_eq!(b.block.encoding, Encoding::Ascii);
+            assert!(b.block.data_uncompressed_len <= u16::MAX as usize);
+        }
+
+        // Going back to the original profile restores the metadata bytes.
+        let options = TranscodeOptions {
+            metadata_compression: Some(CompressionAlgorithm::None),
+            gcode_compression: Some(CompressionAlgorithm::Heatshrink12_4),
+            gcode_encoding: Some(Encoding::MeatpackWithComments),
+            ..Default::default()
+        };
+        let packed = transcode(&unpacked, &options).unwrap();
+        let gcode_start = before[6].range.start;
+        assert_eq!(packed[..gcode_start], binary[..gcode_start]);
+        assert_eq!(
+            binary_to_ascii(&packed, false).unwrap(),
+            binary_to_ascii(binary, false).unwrap()
+        );
+        // MeatPack to ascii and back gives the same gcode.
+        let gcode = |binary: &[u8]| {
+            let (_, blocks) = deserialise_file(binary).unwrap();
+            blocks
+                .iter()
+                .filter(is_gcode)
+                .flat_map(|b| b.block.gcode().unwrap())
+                .collect::<Vec<u8>>()
+        };
+        assert_eq!(gcode(&packed), gcode(binary));
+
+        // Plain meatpack drops the comments.
+        let options = TranscodeOptions {
+            gcode_encoding: Some(Encoding::Meatpack),
+            ..Default::default()
+        };
+        let stripped = transcode(binary, &options).unwrap();
+        let ascii = binary_to_ascii(&stripped, false).unwrap();
+        assert!(ascii.contains("G1 ") && !ascii.contains(";WIPE_START"));
+    }
+}
@@ -0,0 +1,233 @@
+use alloc::vec::Vec;
+
+use crate::components::common::{BinaryGcodeError, BlockKind};
+use crate::components::deserialiser::{
+    unpack_gcode, BlockLayout, DeserialisedResult, Deserialiser,
+};
+use crate::components::metadata::parse_metadata;
+use crate::components::thumbnail::Thumbnail;
+
+/// The version of the specification files are checked against.
+const VERSION: u32 = 1;
+
+/// The blocks every file must have.
+const REQUIRED_BLOCKS: [BlockKind; 4] = [
+    BlockKind::PrinterMetadata,
+    BlockKind::PrintMetadata,
+    BlockKind::SlicerMetadata,
+    BlockKind::GCode,
+];
+
+/// A problem found in a binary gcode file.
+#[derive(Debug)]
+pub struct VerifyProblem {
+    /// The index of the block with the problem, or `None` for the file
+    /// header.
+    pub block: Option<usize>,
+    /// The offset of the block or file header in the file.
+    pub offset: usize,
+    pub error: BinaryGcodeError,
+}
+
+/// Check a binary gcode file against the specification, returning every
+/// problem found. Each block has its checksum checked and its payload
+/// decompressed and decoded, and the blocks must follow the order of the
+/// specification with one of each metadata block. The check carries on
+/// past a bad block as long as the next block can still be found.
+pub fn verify(binary: &[u8]) -> Vec<VerifyProblem> {
+    let mut problems = Vec::new();
+    let mut deserialiser = Deserialiser::default();
+    deserialiser.digest(&binary[..binary.len().min(10)]);
+    let header = match deserialiser.deserialise() {
+        Ok(DeserialisedResult::FileHeader(header)) => header,
+        result => {
+            let error = result
+                .err()
+                .unwrap_or(BinaryGcodeError::UnexpectedEof(10 - binary.len()));
+            problems.push(VerifyProblem {
+                block: None,
+                offset: 0,
+                error,
+            });
+            return problems;
+        }
+    };
+    if header.version != VERSION {
+        problems.push(VerifyProblem {
+            block: None,
+            offset: 0,
+            error: BinaryGcodeError::UnsupportedVersion(header.version),
+        });
+    }
+
+    let mut offset = 10;
+    let mut blocks: Vec<(usize, BlockKind)> = Vec::new();
+    while offset < binary.len() {
+        let mut problem = |error| {
+            problems.push(VerifyProblem {
+                block: Some(blocks.len()),
+                offset,
+                error,
+            })
+        };
+        let layout = match BlockLayout::read(binary, offset, header.checksum) {
+            Ok(layout) => layout,
+            Err(e) => {
+                // Without the block's length the rest can not be found.
+                problem(e);
+                return problems;
+            }
+        };
+        match layout.checksum {
+            Some((stored, computed)) if stored != computed => {
+                problem(BinaryGcodeError::InvalidChecksum(stored, computed));
+            }
+            _ => {}
+        }
+        if let Some(&(_, after)) = blocks.last() {
+            let kind = layout.kind;
+            if after.file_order() > kind.file_order() {
+                problem(BinaryGcodeError::BlockOrder { kind, after });
+            } else if after == kind
+                && !matches!(kind, BlockKind::GCode | BlockKind::Thumbnail)
+            {
+                problem(BinaryGcodeError::DuplicateBlock(kind));
+            }
+        }
+        if let Err(e) = check_payload(binary, &layout) {
+            problem(e);
+        }
+        blocks.push((offset, layout.kind));
+        offset = layout.range.end;
+    }
+
+    for kind in REQUIRED_BLOCKS {
+        if blocks.iter().any(|&(_, k)| k == kind) {
+            continue;
+        }
+        // Point at where the block should have been.
+        let next = blocks
+            .iter()
+            .position(|(_, k)| k.file_order() > kind.file_order());
+        problems.push(VerifyProblem {
+            block: next,
+            offset: next.map_or(binary.len(), |i| blocks[i].0),
+            error: BinaryGcodeError::MissingBlock(kind),
+        });
+    }
+    problems
+}
+
+/// Decode the payload of a block, checking it decompresses to its declared
+/// length and holds what its kind says.
+fn check_payload(
+    binary: &[u8],
+    layout: &BlockLayout,
+) -> Result<(), BinaryGcodeError> {
+    let block = layout.block(binary)?;
+    let data = block.decompress()?;
+    if data.len() != block.data_uncompressed_len {
+        return Err(BinaryGcodeError::LengthMismatch {
+            declared: block.data_uncompressed_len,
+            actual: data.len(),
+        });
+    }
+    match block.kind {
+        BlockKind::GCode => {
+            unpack_gcode(block.encoding, &data, &mut Vec::new())
+        }
+        BlockKind::Thumbnail => {
+            Thumbnail::from_data(&block, data)?.validate_payload()
+        }
+        _ => parse_metadata(&data).map(|_| ()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use crate::components::common::{
+        crc32, BinaryGcodeError, BlockKind, Checksum,
+    };
+    use crate::components::convert::binary_to_ascii;
+    use crate::components::deserialiser::{deserialise_file, BlockLayout};
+
+    #[test]
+    fn verify_reports_each_problem() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        assert!(verify(binary).is_empty());
+
+        // Corrupt a byte of the third block's payload.
+        let (_, blocks) = deserialise_file(binary).unwrap();
+        let mut corrupted = binary.to_vec();
+        corrupted[blocks[2].range.start + 20] ^= 0xff;
+        let problems = verify(&corrupted);
+        assert!(matches!(
+            problems[0].error,
+            BinaryGcodeError::InvalidChecksum(..)
+        ));
+        assert_eq!(problems[0].block, Some(2));
+        assert_eq!(problems[0].offset, blocks[2].range.start);
+
+        // Move the first gcode block to the front.
+        let gcode = blocks
+            .iter()
+            .find(|b| b.block.kind == BlockKind::GCode)
+            .unwrap();
+        let mut reordered = binary[..10].to_vec();
+        reordered.extend_from_slice(&binary[gcode.range.clone()]);
+        for b in blocks.iter().filter(|b| b.range != gcode.range) {
+            reordered.extend_from_slice(&binary[b.range.clone()]);
+        }
+        let problems = verify(&reordered);
+        assert!(matches!(
+            problems[0].error,
+            BinaryGcodeError::BlockOrder {
+                kind: BlockKind::FileMetadata,
+                after: BlockKind::GCode
+            }
+        ));
+        assert_eq!(problems[0].block, Some(1));
+
+        // Cut the file off part way through a block.
+        let problems = verify(&binary[..blocks[1].range.end - 1]);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0].error,
+            BinaryGcodeError::UnexpectedEof(1)
+        ));
+    }
+
+    #[test]
+    fn verify_reports_thumbnail_mismatch() {
+        let binary = include_bytes!("../../test_files/mini_cube_b.bgcode");
+        let (_, blocks) = deserialise_file(binary).unwrap();
+        let thumb = blocks
+            .iter()
+            .position(|b| b.block.kind == BlockKind::Thumbnail)
+            .unwrap();
+
+        // Declare a different width and fix up the checksum to match.
+        let layout = BlockLayout::read(
+            binary,
+            blocks[thumb].range.start,
+            Checksum::Crc32,
+        )
+        .unwrap();
+        let mut mismatched = binary.to_vec();
+        mismatched[layout.parameters.start + 2] ^= 0x01;
+        let crc = crc32(&mismatched[layout.range.start..layout.data.end]);
+        mismatched[layout.data.end..layout.range.end]
+            .copy_from_slice(&crc.to_le_bytes());
+
+        // Decoding still works, only verify reports the mismatch.
+        assert!(binary_to_ascii(&mismatched, false).is_ok());
+        let problems = verify(&mismatched);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0].error,
+            BinaryGcodeError::ThumbnailMismatch { .. }
+        ));
+        assert_eq!(problems[0].block, Some(thumb));
+    }
+}
@@ -3,12 +3,44 @@
 
 #[macro_use]
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 mod components;
 
-pub use components::common::{BinaryGcodeError, BlockKind, Checksum};
-pub use components::convert::{ascii_to_binary, binary_to_ascii};
+pub use components::common::{
+    BinaryGcodeError, BlockKind, Checksum, CompressionAlgorithm, Encoding,
+};
+pub use components::convert::{
+    ascii_to_binary, ascii_to_binary_with_options,
+    ascii_to_binary_with_statistics, binary_to_ascii, AsciiSections,
+    AsciiToBinaryOptions,
+};
+pub use components::crc::Crc32;
 pub use components::deserialiser::{
-    DeserialisedBlock, DeserialisedFileHeader, DeserialisedResult, Deserialiser,
+    deserialise_file, BlockLayout, DeserialisedBlock, DeserialisedFileHeader,
+    DeserialisedResult, Deserialiser, FramedBlock,
+};
+pub use components::gcode::{
+    parse_gcode, Arc, Axes, Command, GcodeLine, GcodeLines, Move, Word, Words,
+};
+pub use components::layers::{
+    embed_layer_index, Layer, LayerIndex, LAYER_INDEX_KEY,
+};
+pub use components::metadata::{get_metadata, parse_metadata, set_metadata};
+pub use components::preview::{
+    add_preview_thumbnail, render_preview, PreviewView,
+};
+pub use components::resume::resume_from_layer;
+pub use components::serialiser::{
+    serialise_block, serialise_block_auto, serialise_block_with_level,
+    serialise_file_header, BlockStatistics, CompressionLevel,
+};
+pub use components::stats::{FilamentUsage, PrintStatistics};
+pub use components::thumbnail::{
+    edit_thumbnails, thumbnails, ImageInfo, RgbaImage, Thumbnail,
+    ThumbnailEdit, ThumbnailFormat,
 };
-pub use components::serialiser::{serialise_block, serialise_file_header};
+pub use components::time::{regenerate_progress, MachineLimits, TimeEstimate};
+pub use components::transcode::{transcode, TranscodeOptions};
+pub use components::verify::{verify, VerifyProblem};